@@ -1,10 +1,20 @@
 use std::{
+    env,
+    ffi::OsString,
     io::{self, Write},
+    net::Ipv6Addr,
+    num::NonZeroUsize,
     path::PathBuf,
 };
 
 use lexopt::{Arg, Parser, ValueExt};
 
+use crate::{
+    config::{Config, TlsPaths},
+    load::LoadOptions,
+    server::{hash_password, CachePolicy, Listen},
+};
+
 const APP_NAME: &str = "tachi-remote";
 
 macro_rules! format_help {
@@ -14,14 +24,42 @@ macro_rules! format_help {
                 "{app_name} ", env!("CARGO_PKG_VERSION"), "\n",
                 "\n",
                 "USAGE:\n",
-                "    {app_name} [options] <port> [path]\n",
+                "    {app_name} [options] <port> [paths...]\n",
+                "    {app_name} [options] --listen <addr>... [paths...]\n",
+                "    {app_name} --config <path> [options] [paths...]\n",
                 "\n",
                 "ARGS:\n",
-                "    <port>          the port to listen on\n",
-                "    [path]          path to the library directory, defaults to the current working directory\n",
+                "    <port>                             the port to listen on, on all addresses, unless addresses are set with --listen or the config\n",
+                "    [paths...]                         paths to the library directories, defaults to the current working directory\n",
                 "\n",
                 "OPTIONS:\n",
-                "    -h, --help      print help\n",
+                "    -h, --help                         print help\n",
+                "    -c, --config <path>                read options from a TOML config file, also set with TACHI_REMOTE_CONFIG\n",
+                "        --print-config                 print the effective configuration and exit\n",
+                "    -l, --listen <addr>                listen on addr instead of <port>, can be passed multiple times, one of:\n",
+                "                                           <ip>:<port>  a TCP address, e.g. 127.0.0.1:8080 or [::1]:8080\n",
+                "                                           unix:<path>  a Unix socket, always plain HTTP\n",
+                "                                           systemd      the sockets passed with systemd socket activation\n",
+                "        --cache-control <route=value>  set the Cache-Control header for a route type\n",
+                "                                       (library, manga, cover or page), an empty value disables it\n",
+                "    -t, --threads <count>              serve using a multi-threaded runtime with count worker threads\n",
+                "    -w, --watch                        reload manga when their files change\n",
+                "        --admin-token <token>          enable the /_admin routes, authenticated with this bearer token\n",
+                "        --auth <path>                  require clients to authenticate with the credentials in a TOML file\n",
+                "        --hash-password                hash a password read from stdin for the credentials file and exit\n",
+                "        --tls-cert <path>              serve HTTPS using the PEM certificate chain at path, reloaded when it changes\n",
+                "        --tls-key <path>               the PEM private key of the TLS certificate\n",
+                "        --max-page-size <bytes>        maximum uncompressed size of a page in an archive, defaults to 256 MiB\n",
+                "        --extract-cache-size <bytes>   memory kept for pages extracted from solid archives or converted from PDFs,\n",
+                "                                       defaults to 512 MiB, 0 extracts pages on every request\n",
+                "        --containment <policy>         where chapter and cover paths may point to, one of:\n",
+                "                                           strict   inside the manga directory (default)\n",
+                "                                           relaxed  inside the library directory\n",
+                "        --duplicates <policy>          what to do with manga sharing an id, one of:\n",
+                "                                           fail     refuse to start (default)\n",
+                "                                           first    keep the first manga found\n",
+                "                                           last     keep the last manga found\n",
+                "                                           suffix   keep all, appending a suffix to later ids\n",
             ),
             $($v)*
         )
@@ -30,54 +68,144 @@ macro_rules! format_help {
 
 #[derive(Debug)]
 pub struct Args {
-    pub port: u16,
-    pub path: PathBuf,
+    pub listen: Vec<Listen>,
+    pub paths: Vec<PathBuf>,
+    pub cache: CachePolicy,
+    pub threads: Option<NonZeroUsize>,
+    pub watch: bool,
+    pub admin_token: Option<String>,
+    pub auth: Option<PathBuf>,
+    /// TLS certificate and private key paths.
+    pub tls: Option<(PathBuf, PathBuf)>,
+    pub extract_cache_size: u64,
+    pub load: LoadOptions,
 }
 
 impl Args {
-    pub fn parse() -> Result<Option<Self>, lexopt::Error> {
-        #[derive(Debug, Default)]
-        struct Partial {
-            port: Option<u16>,
-            path: Option<PathBuf>,
-        }
-
-        let mut args = Partial::default();
+    /// Parses the command line, merged over the environment and config file.
+    pub fn parse() -> anyhow::Result<Option<Self>> {
+        let mut args = Config::default();
+        let mut values = Vec::<OsString>::new();
+        let mut config_path = env::var_os("TACHI_REMOTE_CONFIG").map(PathBuf::from);
+        let mut print_config = false;
+        let mut tls_cert = None;
+        let mut tls_key = None;
 
         let mut parser = Parser::from_env();
-        let mut do_help = true;
+        // without arguments, only start if the environment says how
+        let mut do_help = config_path.is_none() && env::var_os("TACHI_REMOTE_LISTEN").is_none();
 
         while let Some(arg) = parser.next()? {
             do_help = false;
             match arg {
-                Arg::Value(arg) => match &mut args {
-                    Partial { port: None, .. } => args.port = Some(arg.parse()?),
-                    Partial {
-                        port: Some(_),
-                        path: None,
-                    } => args.path = Some(PathBuf::from(arg)),
-                    _ => return Err(Arg::Value(arg).unexpected()),
-                },
+                Arg::Value(arg) => values.push(arg),
                 Arg::Short('h') | Arg::Long("help") => {
                     do_help = true;
                     break;
                 }
-                arg => return Err(arg.unexpected()),
+                Arg::Short('c') | Arg::Long("config") => {
+                    config_path = Some(parser.value()?.into());
+                }
+                Arg::Long("print-config") => {
+                    print_config = true;
+                }
+                Arg::Short('l') | Arg::Long("listen") => {
+                    let listen = parser.value()?.parse()?;
+                    args.listen.get_or_insert_with(Vec::new).push(listen);
+                }
+                Arg::Long("cache-control") => {
+                    let (route, value) = parser.value()?.parse_with(|v| {
+                        CachePolicy::default().set(v)?;
+                        let (route, value) = v.split_once('=').expect("checked by set");
+                        Ok::<_, String>((route.to_owned(), value.to_owned()))
+                    })?;
+                    args.cache_control.insert(route, value);
+                }
+                Arg::Short('t') | Arg::Long("threads") => {
+                    args.threads = Some(parser.value()?.parse()?);
+                }
+                Arg::Short('w') | Arg::Long("watch") => {
+                    args.watch = Some(true);
+                }
+                Arg::Long("admin-token") => {
+                    args.admin_token = Some(parser.value()?.string()?);
+                }
+                Arg::Long("auth") => {
+                    args.auth = Some(parser.value()?.into());
+                }
+                Arg::Long("hash-password") => {
+                    let mut password = String::new();
+                    io::stdin().read_line(&mut password)?;
+                    println!(
+                        "{}",
+                        hash_password(password.trim_end_matches(['\r', '\n']))?
+                    );
+                    return Ok(None);
+                }
+                Arg::Long("tls-cert") => {
+                    tls_cert = Some(parser.value()?.into());
+                }
+                Arg::Long("tls-key") => {
+                    tls_key = Some(parser.value()?.into());
+                }
+                Arg::Long("max-page-size") => {
+                    args.max_page_size = Some(parser.value()?.parse()?);
+                }
+                Arg::Long("extract-cache-size") => {
+                    args.extract_cache_size = Some(parser.value()?.parse()?);
+                }
+                Arg::Long("containment") => {
+                    args.containment = Some(parser.value()?.parse()?);
+                }
+                Arg::Long("duplicates") => {
+                    args.duplicates = Some(parser.value()?.parse()?);
+                }
+                arg => return Err(arg.unexpected().into()),
             }
         }
 
         if do_help {
-            match io::stdout().write_fmt(format_help!(
+            io::stdout().write_fmt(format_help!(
                 app_name = parser.bin_name().unwrap_or(APP_NAME),
-            )) {
-                Ok(()) => return Ok(None),
-                Err(e) => return Err(lexopt::Error::Custom(e.into())),
+            ))?;
+            return Ok(None);
+        }
+
+        args.tls = match (tls_cert, tls_key) {
+            (Some(cert), Some(key)) => Some(TlsPaths { cert, key }),
+            (None, None) => None,
+            _ => anyhow::bail!("--tls-cert and --tls-key must be used together"),
+        };
+
+        let mut config = Config::defaults();
+        if let Some(path) = &config_path {
+            config.merge(Config::load(path)?);
+        }
+        config.merge(Config::from_env()?);
+
+        // the first value is the port, unless addresses are given with --listen,
+        // the config file or the environment, in which case it is a path
+        let mut values = values.into_iter().peekable();
+        if args.listen.is_none() && config.listen.is_none() {
+            if let Some(port) =
+                values.next_if(|v| v.to_str().is_some_and(|v| v.parse::<u16>().is_ok()))
+            {
+                let port: u16 = port.parse()?;
+                args.listen = Some(vec![Listen::Tcp((Ipv6Addr::UNSPECIFIED, port).into())]);
             }
         }
+        let paths = values.map(PathBuf::from).collect::<Vec<_>>();
+        if !paths.is_empty() {
+            args.paths = Some(paths);
+        }
+
+        config.merge(args);
+
+        if print_config {
+            config.print()?;
+            return Ok(None);
+        }
 
-        Ok(Some(Args {
-            port: args.port.ok_or("missing argument 'port'")?,
-            path: args.path.unwrap_or_else(|| PathBuf::from(".")),
-        }))
+        config.into_args().map(Some)
     }
 }
@@ -0,0 +1,275 @@
+use std::{
+    collections::BTreeMap,
+    env::{self, VarError},
+    fs,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    args::Args,
+    load::{Containment, Duplicates, LoadOptions},
+    server::{CachePolicy, Listen, DEFAULT_EXTRACT_CACHE_SIZE},
+};
+
+/// Prefix of the environment variables overriding the config file.
+const ENV_PREFIX: &str = "TACHI_REMOTE_";
+
+/// Server configuration, as read from a config file, the environment or the command line.
+///
+/// Every field is optional so configurations can be layered with [`Config::merge`].
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listen: Option<Vec<Listen>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paths: Option<Vec<PathBuf>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threads: Option<NonZeroUsize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watch: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_page_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extract_cache_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub containment: Option<Containment>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicates: Option<Duplicates>,
+    /// `Cache-Control` values by route type, an empty value disables the header.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub cache_control: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsPaths>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsPaths {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl Config {
+    /// The configuration used for values that are not set anywhere else.
+    pub fn defaults() -> Self {
+        let load = LoadOptions::default();
+        let cache = CachePolicy::default();
+
+        Self {
+            listen: None,
+            paths: Some(vec![PathBuf::from(".")]),
+            threads: None,
+            watch: Some(false),
+            admin_token: None,
+            auth: None,
+            max_page_size: Some(load.max_page_size),
+            extract_cache_size: Some(DEFAULT_EXTRACT_CACHE_SIZE),
+            containment: Some(load.containment),
+            duplicates: Some(load.duplicates),
+            cache_control: cache
+                .routes()
+                .into_iter()
+                .map(|(route, value)| {
+                    let value = value.as_ref().and_then(|v| v.to_str().ok()).unwrap_or("");
+                    (route.to_owned(), value.to_owned())
+                })
+                .collect(),
+            tls: None,
+        }
+    }
+
+    /// Reads a TOML config file.
+    ///
+    /// Relative paths in the file are relative to the directory containing it.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let ctx = || format!("{:?}: error reading config file", path);
+        let mut config: Self =
+            toml::from_slice(&fs::read(path).with_context(ctx)?).with_context(ctx)?;
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let resolve = |path: &mut PathBuf| *path = dir.join(&*path);
+        config.paths.iter_mut().flatten().for_each(resolve);
+        config.auth.iter_mut().for_each(resolve);
+        if let Some(tls) = &mut config.tls {
+            resolve(&mut tls.cert);
+            resolve(&mut tls.key);
+        }
+        #[cfg(unix)]
+        for listen in config.listen.iter_mut().flatten() {
+            if let Listen::Unix(path) = listen {
+                resolve(path);
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Reads the configuration from `TACHI_REMOTE_*` environment variables.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut config = Self {
+            listen: env_var("LISTEN")?
+                .map(|v| {
+                    v.split(',')
+                        .map(|v| parse_env("LISTEN", v.trim()))
+                        .collect()
+                })
+                .transpose()?,
+            paths: env::var_os(format!("{}PATHS", ENV_PREFIX))
+                .map(|v| env::split_paths(&v).collect()),
+            threads: env_parse("THREADS")?,
+            watch: env_bool("WATCH")?,
+            admin_token: env_var("ADMIN_TOKEN")?,
+            auth: env_var("AUTH")?.map(PathBuf::from),
+            max_page_size: env_parse("MAX_PAGE_SIZE")?,
+            extract_cache_size: env_parse("EXTRACT_CACHE_SIZE")?,
+            containment: env_parse("CONTAINMENT")?,
+            duplicates: env_parse("DUPLICATES")?,
+            cache_control: BTreeMap::new(),
+            tls: match (env_var("TLS_CERT")?, env_var("TLS_KEY")?) {
+                (Some(cert), Some(key)) => Some(TlsPaths {
+                    cert: cert.into(),
+                    key: key.into(),
+                }),
+                (None, None) => None,
+                _ => anyhow::bail!(
+                    "{0}TLS_CERT and {0}TLS_KEY must be set together",
+                    ENV_PREFIX
+                ),
+            },
+        };
+
+        for route in CachePolicy::default().routes().map(|(route, _)| route) {
+            let name = format!("CACHE_CONTROL_{}", route.to_ascii_uppercase());
+            if let Some(value) = env_var(&name)? {
+                config.cache_control.insert(route.to_owned(), value);
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Overrides the values of `self` with the ones set in `other`.
+    pub fn merge(&mut self, other: Self) {
+        let Self {
+            listen,
+            paths,
+            threads,
+            watch,
+            admin_token,
+            auth,
+            max_page_size,
+            extract_cache_size,
+            containment,
+            duplicates,
+            cache_control,
+            tls,
+        } = other;
+
+        fn set<T>(value: &mut Option<T>, other: Option<T>) {
+            if other.is_some() {
+                *value = other;
+            }
+        }
+
+        set(&mut self.listen, listen);
+        set(&mut self.paths, paths);
+        set(&mut self.threads, threads);
+        set(&mut self.watch, watch);
+        set(&mut self.admin_token, admin_token);
+        set(&mut self.auth, auth);
+        set(&mut self.max_page_size, max_page_size);
+        set(&mut self.extract_cache_size, extract_cache_size);
+        set(&mut self.containment, containment);
+        set(&mut self.duplicates, duplicates);
+        self.cache_control.extend(cache_control);
+        set(&mut self.tls, tls);
+    }
+
+    /// Prints the configuration as TOML, hiding secrets.
+    pub fn print(&self) -> anyhow::Result<()> {
+        let mut config = self.clone();
+        if let Some(token) = &mut config.admin_token {
+            *token = "<redacted>".to_owned();
+        }
+        print!("{}", toml::to_string(&config)?);
+        Ok(())
+    }
+
+    pub fn into_args(self) -> anyhow::Result<Args> {
+        let defaults = LoadOptions::default();
+
+        let mut cache = CachePolicy::default();
+        for (route, value) in &self.cache_control {
+            cache
+                .set(&format!("{}={}", route, value))
+                .map_err(anyhow::Error::msg)?;
+        }
+
+        Ok(Args {
+            listen: self.listen.context("missing argument 'port'")?,
+            paths: self.paths.unwrap_or_default(),
+            cache,
+            threads: self.threads,
+            watch: self.watch.unwrap_or_default(),
+            admin_token: self.admin_token,
+            auth: self.auth,
+            tls: self.tls.map(|tls| (tls.cert, tls.key)),
+            extract_cache_size: self
+                .extract_cache_size
+                .unwrap_or(DEFAULT_EXTRACT_CACHE_SIZE),
+            load: LoadOptions {
+                max_page_size: self.max_page_size.unwrap_or(defaults.max_page_size),
+                containment: self.containment.unwrap_or(defaults.containment),
+                duplicates: self.duplicates.unwrap_or(defaults.duplicates),
+            },
+        })
+    }
+}
+
+fn env_var(name: &str) -> anyhow::Result<Option<String>> {
+    let name = format!("{}{}", ENV_PREFIX, name);
+    match env::var(&name) {
+        Ok(v) => Ok(Some(v)),
+        Err(VarError::NotPresent) => Ok(None),
+        Err(e) => Err(e).context(name),
+    }
+}
+
+fn env_parse<T>(name: &str) -> anyhow::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    env_var(name)?.map(|v| parse_env(name, &v)).transpose()
+}
+
+/// Reads a boolean variable, accepting 1 and 0 as well as true and false.
+fn env_bool(name: &str) -> anyhow::Result<Option<bool>> {
+    env_var(name)?
+        .map(|v| match v.trim() {
+            "true" | "1" => Ok(true),
+            "false" | "0" => Ok(false),
+            _ => anyhow::bail!("{}{}: expected true, false, 1 or 0", ENV_PREFIX, name),
+        })
+        .transpose()
+}
+
+fn parse_env<T>(name: &str, value: &str) -> anyhow::Result<T>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|e| anyhow::anyhow!("{}{}: {}", ENV_PREFIX, name, e))
+}
@@ -1,3 +1,5 @@
+#[cfg(feature = "watch")]
+use std::collections::BTreeSet;
 use std::{
     borrow::Cow,
     collections::HashMap,
@@ -6,80 +8,396 @@ use std::{
     io::{self, Read},
     mem,
     path::{Path, PathBuf},
+    slice,
+    str::FromStr,
+    sync::{Arc, Mutex, PoisonError, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::Context;
-use log::error;
+use log::{error, info, warn};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
 
 use serde::{de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
 use walkdir::WalkDir;
 
-use crate::server::JsonBytes;
+#[cfg(feature = "pdf")]
+use crate::pdf::PdfPage;
+use crate::{
+    search::{normalize, normalize_list, SearchIndex},
+    server::JsonBytes,
+};
 
-pub fn load_library<P: AsRef<Path>>(path: &[P]) -> anyhow::Result<LibraryEntry> {
-    let mut walk = WalkDir::new(&path[0])
-        .max_open(128)
-        .follow_links(true)
-        .into_iter()
-        .filter_entry(|entry| entry.file_type().is_dir());
+/// Ids that would be shadowed by other routes.
+const RESERVED_IDS: &[&str] = &["_admin", "latest", "search"];
+
+/// Options controlling how the library is loaded.
+#[derive(Debug, Clone)]
+pub struct LoadOptions {
+    /// Maximum uncompressed size of a page inside an archive, in bytes.
+    pub max_page_size: u64,
+    /// Where chapter, page and cover paths are allowed to point to.
+    pub containment: Containment,
+    /// What to do with manga sharing the same id.
+    pub duplicates: Duplicates,
+}
 
-    let mut lib_buf = vec![b'['];
-    let mut mangas: HashMap<String, MangaEntry> = HashMap::new();
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self {
+            max_page_size: 256 * 1024 * 1024,
+            containment: Containment::default(),
+            duplicates: Duplicates::default(),
+        }
+    }
+}
+
+/// Restricts the files a manga can expose.
+///
+/// Paths are checked after resolving symlinks, so a symlink pointing
+/// outside of the allowed directories is rejected as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Containment {
+    /// Paths must stay within the manga directory.
+    #[default]
+    Strict,
+    /// Paths must stay within one of the library roots.
+    Relaxed,
+}
+
+impl FromStr for Containment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(Self::Strict),
+            "relaxed" => Ok(Self::Relaxed),
+            _ => Err(format!(
+                "unknown containment policy {:?}, expected strict or relaxed",
+                s
+            )),
+        }
+    }
+}
+
+/// How to handle manga with the same id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Duplicates {
+    /// Fail to load the library.
+    #[default]
+    Fail,
+    /// Keep the first manga found, ignoring later ones.
+    #[serde(rename = "first")]
+    KeepFirst,
+    /// Keep the last manga found, replacing earlier ones.
+    #[serde(rename = "last")]
+    KeepLast,
+    /// Keep all manga, appending a numeric suffix to the ids of later ones.
+    Suffix,
+}
+
+impl FromStr for Duplicates {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(Self::Fail),
+            "first" => Ok(Self::KeepFirst),
+            "last" => Ok(Self::KeepLast),
+            "suffix" => Ok(Self::Suffix),
+            _ => Err(format!(
+                "unknown duplicate policy {:?}, expected fail, first, last or suffix",
+                s
+            )),
+        }
+    }
+}
+
+pub fn load_library<P: AsRef<Path>>(
+    path: &[P],
+    opts: &LoadOptions,
+) -> anyhow::Result<LibraryEntry> {
+    let mut errors = 0;
+    let roots = path
+        .iter()
+        .filter_map(|path| {
+            let path = path.as_ref();
+            match path.canonicalize() {
+                Ok(root) if root.is_dir() => Some(root),
+                Ok(_) => {
+                    error!("{:?}: library root is not a directory", path);
+                    errors += 1;
+                    None
+                }
+                Err(e) => {
+                    error!("{:?}: error resolving library root: {}", path, e);
+                    errors += 1;
+                    None
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+    anyhow::ensure!(
+        !roots.is_empty(),
+        "no usable library root in {:?}",
+        path.iter().map(AsRef::as_ref).collect::<Vec<&Path>>()
+    );
+
+    let mut order: Vec<String> = Vec::new();
+    let mut mangas: HashMap<String, Arc<MangaEntry>> = HashMap::new();
 
     let mut read_buf = Vec::new();
-    while let Some(entry) = walk.next() {
-        let lib_buf_pos = lib_buf.len();
-
-        let res = (|| -> anyhow::Result<()> {
-            let mut path = entry?.into_path();
-
-            if let Some(manga) = load_manga(&mut path, &mut read_buf) {
-                walk.skip_current_dir();
-                let mut manga =
-                    manga.with_context(|| anyhow::anyhow!("{:?}: error reading manga", path))?;
-
-                struct LibraryEntrySer<'a>(&'a Manga<'a>);
-                impl<'a> Serialize for LibraryEntrySer<'a> {
-                    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
-                        use serde::ser::SerializeStruct;
-                        let mut ser = ser.serialize_struct("LibraryEntrySer", 2)?;
-                        ser.serialize_field("id", &self.0.id)?;
-                        ser.serialize_field("title", &self.0.title)?;
-                        ser.end()
-                    }
+    for root in &roots {
+        let mut walk = WalkDir::new(root)
+            .max_open(128)
+            .follow_links(true)
+            .into_iter()
+            .filter_entry(|entry| entry.file_type().is_dir());
+
+        let mut found = 0usize;
+        while let Some(entry) = walk.next() {
+            let res = entry.map_err(Into::into).and_then(|entry| {
+                let manga = read_manga(
+                    entry.into_path(),
+                    &mut read_buf,
+                    &roots,
+                    &HashMap::new(),
+                    opts,
+                );
+                if manga.is_some() {
+                    walk.skip_current_dir();
+                }
+                manga.transpose()
+            });
+
+            let (id, manga) = match res {
+                Ok(Some(v)) => v,
+                Ok(None) => continue,
+                Err(e) => {
+                    error!("{:?}: error traversing directory: {:#}", root, e);
+                    errors += 1;
+                    continue;
                 }
-                serde_json::to_writer(&mut lib_buf, &LibraryEntrySer(&manga))?;
-                lib_buf.push(b',');
+            };
+            found += 1;
+
+            if let Inserted::New(id) = insert_manga(&mut mangas, id, Arc::new(manga), opts)? {
+                order.push(id);
+            }
+        }
 
-                mangas.insert(
-                    mem::take(&mut manga.id).into_owned(),
-                    MangaEntry::new(manga)?,
+        info!("{:?}: found {} manga", root, found);
+    }
+
+    Ok(LibraryEntry {
+        json: library_json(&order, &mangas, false)?,
+        json_details: library_json(&order, &mangas, true)?,
+        search: SearchIndex::new(order.iter().map(|id| &*mangas[id])),
+        mangas,
+        order,
+        #[cfg(feature = "watch")]
+        roots,
+        errors,
+        modified: SystemTime::now(),
+    })
+}
+
+/// Where [`insert_manga`] put a manga.
+enum Inserted {
+    /// Under this id, which wasn't in the library yet.
+    New(String),
+    /// In place of the manga with the same id.
+    Replaced,
+    /// Nowhere, a manga with the same id is kept instead.
+    Skipped,
+}
+
+/// Adds a manga to the library, resolving conflicting ids according to the duplicates policy.
+///
+/// Reserved ids conflict with the routes using them, which are always kept.
+fn insert_manga(
+    mangas: &mut HashMap<String, Arc<MangaEntry>>,
+    id: String,
+    manga: Arc<MangaEntry>,
+    opts: &LoadOptions,
+) -> anyhow::Result<Inserted> {
+    if RESERVED_IDS.contains(&&*id) {
+        let path = &manga.path;
+        return match opts.duplicates {
+            Duplicates::Fail => {
+                anyhow::bail!("manga id {:?} in {:?} is reserved", id, path)
+            }
+            Duplicates::KeepFirst | Duplicates::KeepLast => {
+                warn!("manga id {:?} in {:?} is reserved, skipping it", id, path);
+                Ok(Inserted::Skipped)
+            }
+            Duplicates::Suffix => {
+                let renamed = unused_id(mangas, &id);
+                warn!(
+                    "manga id {:?} in {:?} is reserved, renaming it to {:?}",
+                    id, path, renamed
                 );
+                mangas.insert(renamed.clone(), manga);
+                Ok(Inserted::New(renamed))
             }
+        };
+    }
 
-            Ok(())
-        })();
+    let Some(existing) = mangas.get(&id) else {
+        mangas.insert(id.clone(), manga);
+        return Ok(Inserted::New(id));
+    };
 
-        if let Err(e) = res {
-            lib_buf.truncate(lib_buf_pos);
-            error!("error traversing directory: {:#}", e);
+    let (first, second) = (&existing.path, &manga.path);
+    match opts.duplicates {
+        Duplicates::Fail => anyhow::bail!(
+            "duplicate manga id {:?} in {:?} and {:?}",
+            id,
+            first,
+            second
+        ),
+        Duplicates::KeepFirst => {
+            warn!(
+                "duplicate manga id {:?} in {:?} and {:?}, keeping the first",
+                id, first, second
+            );
+            Ok(Inserted::Skipped)
+        }
+        Duplicates::KeepLast => {
+            warn!(
+                "duplicate manga id {:?} in {:?} and {:?}, keeping the last",
+                id, first, second
+            );
+            mangas.insert(id, manga);
+            Ok(Inserted::Replaced)
+        }
+        Duplicates::Suffix => {
+            let renamed = unused_id(mangas, &id);
+            warn!(
+                "duplicate manga id {:?} in {:?} and {:?}, renaming the last to {:?}",
+                id, first, second, renamed
+            );
+            mangas.insert(renamed.clone(), manga);
+            Ok(Inserted::New(renamed))
         }
     }
+}
+
+/// Appends the first numeric suffix to `id` that makes it unique.
+fn unused_id(mangas: &HashMap<String, Arc<MangaEntry>>, id: &str) -> String {
+    (2..)
+        .map(|i| format!("{}-{}", id, i))
+        .find(|id| !mangas.contains_key(id))
+        .expect("ran out of suffixes")
+}
+
+/// A manga in a library listing, with only its id and title unless `details` is set.
+struct LibraryEntrySer<'a> {
+    id: &'a str,
+    manga: &'a MangaEntry,
+    details: bool,
+}
+
+impl<'a> Serialize for LibraryEntrySer<'a> {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let Self { id, manga, details } = *self;
+
+        let mut ser = ser.serialize_struct("LibraryEntrySer", if details { 7 } else { 2 })?;
+        ser.serialize_field("id", id)?;
+        ser.serialize_field("title", &manga.title)?;
+        if !details {
+            return ser.end();
+        }
 
-    if let Some(b',') = lib_buf.last() {
-        lib_buf.pop();
+        if manga.status.is_unknown() {
+            ser.skip_field("status")?;
+        } else {
+            ser.serialize_field("status", &manga.status)?;
+        }
+        for (key, value) in [("authors", &manga.authors), ("tags", &manga.tags)] {
+            if value.is_empty() {
+                ser.skip_field(key)?;
+            } else {
+                ser.serialize_field(key, value)?;
+            }
+        }
+        ser.serialize_field("chapterCount", &manga.chapters.len())?;
+        match manga.cover {
+            Some(_) => ser.serialize_field("thumbnailUrl", &format!("{}/cover", manga_url(id)))?,
+            None => ser.skip_field("thumbnailUrl")?,
+        }
+        ser.end()
     }
-    lib_buf.push(b']');
+}
 
-    Ok(LibraryEntry {
-        json: lib_buf.into(),
-        mangas,
-    })
+/// The URL path of a manga, relative to the server root.
+fn manga_url(id: &str) -> String {
+    format!("/{}", utf8_percent_encode(id, PATH_SEGMENT))
+}
+
+/// Characters escaped in URL path segments.
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Serializes the library listing, in the order the manga were found.
+fn library_json(
+    order: &[String],
+    mangas: &HashMap<String, Arc<MangaEntry>>,
+    details: bool,
+) -> anyhow::Result<JsonBytes> {
+    let lib = order
+        .iter()
+        .map(|id| LibraryEntrySer {
+            id,
+            manga: &mangas[id],
+            details,
+        })
+        .collect::<Vec<_>>();
+
+    Ok(serde_json::to_vec(&lib)?.into())
+}
+
+/// Loads the manga in the directory `path` along with its id,
+/// returning `None` if the directory has no `info.toml`.
+///
+/// Chapters found in `loaded` by their canonical path reuse those pages instead of loading them again.
+fn read_manga(
+    mut path: PathBuf,
+    read_buf: &mut Vec<u8>,
+    roots: &[PathBuf],
+    loaded: &HashMap<PathBuf, Pages>,
+    opts: &LoadOptions,
+) -> Option<anyhow::Result<(String, MangaEntry)>> {
+    let manga = load_manga(&mut path, read_buf, roots, loaded, opts)?;
+
+    Some((|| {
+        let mut manga =
+            manga.with_context(|| anyhow::anyhow!("{:?}: error reading manga", path))?;
+        let id = mem::take(&mut manga.id).into_owned();
+        Ok((id, MangaEntry::new(path, manga)?))
+    })())
 }
 
 fn load_manga<'a>(
     path: &mut PathBuf,
     read_buf: &'a mut Vec<u8>,
+    roots: &[PathBuf],
+    loaded: &HashMap<PathBuf, Pages>,
+    opts: &LoadOptions,
 ) -> Option<anyhow::Result<Manga<'a>>> {
     path.push("info.toml");
     let file = File::open(&path);
@@ -97,46 +415,167 @@ fn load_manga<'a>(
 
         let mut manga: Manga = toml::from_slice(read_buf)?;
 
+        let manga_dir = path
+            .canonicalize()
+            .with_context(|| format!("{:?}: error resolving manga directory", path))?;
+        let allowed = match opts.containment {
+            Containment::Strict => slice::from_ref(&manga_dir),
+            Containment::Relaxed => roots,
+        };
+
         for (i, ch) in manga.chapters.iter_mut().enumerate() {
-            ch.pages = load_chapter(path.join(&ch.path))
+            contained_path(&manga_dir, &ch.path, allowed)
+                .and_then(|path| {
+                    ch.pages = match loaded.get(&path) {
+                        Some(pages) => pages.clone(),
+                        None => load_chapter(path.clone(), allowed, opts)?,
+                    };
+                    ch.path = Cow::Owned(path);
+                    Ok(())
+                })
                 .with_context(|| format!("{:?} (#{})", ch.path, i))?;
         }
 
-        if let Some(Cover::File(cover)) = &mut manga.cover {
-            path.push(&cover);
-            *cover = mem::replace(path, PathBuf::new());
+        if let Some(Cover::File(cover)) = &manga.cover {
+            // validate_manga replaces the fallback if the chapter has no pages
+            manga.cover = Some(match manga_dir.join(cover).canonicalize() {
+                Ok(resolved) => Cover::File(
+                    check_contained(cover, resolved, allowed)
+                        .with_context(|| format!("cover {:?}", cover))?,
+                ),
+                Err(e) => {
+                    warn!(
+                        "{:?}: cover {:?} can't be resolved, using the first page instead: {}",
+                        path, cover, e
+                    );
+                    Cover::Page { ch: 0, pg: 0 }
+                }
+            });
         }
 
+        validate_manga(path, &mut manga);
+
         Ok(manga)
     })())
 }
 
-fn load_chapter(path: PathBuf) -> anyhow::Result<Pages> {
+/// Checks a loaded manga for problems that would only show up when serving it,
+/// logging them and falling back to a usable cover if needed.
+fn validate_manga(path: &Path, manga: &mut Manga) {
+    for (i, ch) in manga.chapters.iter().enumerate() {
+        if ch.pages.is_empty() {
+            warn!("{:?}: chapter {:?} (#{}) has no pages", path, ch.path, i);
+        }
+
+        #[cfg(feature = "zip")]
+        if let Pages::Zip(_, pages) = &ch.pages {
+            use rc_zip::Method;
+
+            let unsupported = pages
+                .iter()
+                .filter(|page| !matches!(page.method, Method::Store | Method::Deflate))
+                .count();
+            if unsupported > 0 {
+                warn!(
+                    "{:?}: chapter {:?} (#{}) has {} pages with unsupported compression methods",
+                    path, ch.path, i, unsupported
+                );
+            }
+        }
+    }
+
+    if let Some(Cover::Page { ch, pg }) = manga.cover {
+        let valid = manga
+            .chapters
+            .get(ch)
+            .is_some_and(|ch| pg < ch.pages.len() as usize);
+
+        if !valid {
+            let fallback = manga.chapters.iter().position(|ch| !ch.pages.is_empty());
+            match fallback {
+                Some(fallback) => {
+                    warn!(
+                        "{:?}: cover (chapter #{}, page #{}) does not exist, using the first page of chapter #{}",
+                        path, ch, pg, fallback
+                    );
+                    manga.cover = Some(Cover::Page {
+                        ch: fallback,
+                        pg: 0,
+                    });
+                }
+                None => {
+                    error!(
+                        "{:?}: cover (chapter #{}, page #{}) does not exist, and there are no pages to use instead",
+                        path, ch, pg
+                    );
+                    manga.cover = None;
+                }
+            }
+        }
+    }
+}
+
+/// Resolves `path` relative to `dir`, ensuring that it stays within one of the `allowed` directories.
+///
+/// `dir` and `allowed` must already be canonical.
+fn contained_path(dir: &Path, path: &Path, allowed: &[PathBuf]) -> anyhow::Result<PathBuf> {
+    let resolved = dir
+        .join(path)
+        .canonicalize()
+        .with_context(|| format!("{:?}: error resolving path", path))?;
+    check_contained(path, resolved, allowed)
+}
+
+/// Checks that `resolved`, the canonical form of `path`, is inside one of the `allowed` directories.
+fn check_contained(path: &Path, resolved: PathBuf, allowed: &[PathBuf]) -> anyhow::Result<PathBuf> {
+    anyhow::ensure!(
+        allowed.iter().any(|dir| resolved.starts_with(dir)),
+        "{:?} resolves to {:?}, which is outside of {:?}",
+        path,
+        resolved,
+        allowed
+    );
+
+    Ok(resolved)
+}
+
+fn load_chapter(path: PathBuf, allowed: &[PathBuf], opts: &LoadOptions) -> anyhow::Result<Pages> {
     if path.is_dir() {
-        load_pages_dir(path)
+        load_pages_dir(path, allowed)
     } else {
-        load_pages_file(path)
+        load_pages_file(path, opts)
     }
 }
 
-fn load_pages_dir(path: PathBuf) -> anyhow::Result<Pages> {
+fn load_pages_dir(path: PathBuf, allowed: &[PathBuf]) -> anyhow::Result<Pages> {
     let dir = path.read_dir()?;
 
     let mut pages = Vec::new();
     for entry in dir {
         let entry = entry?;
-        let path = entry.path();
-        if fs::metadata(&path)?.is_file() {
-            pages.push(path);
+        let mut page = entry.path();
+        if entry.file_type()?.is_symlink() {
+            // serve the target that was checked, not whatever the link points to later
+            page = contained_path(&path, &page, allowed)?;
+        }
+        let meta = fs::metadata(&page)?;
+        if meta.is_file() {
+            pages.push((entry.file_name(), page, meta.len()));
         }
     }
 
+    // in the order of the directory entries, whatever links point to
     pages.sort_unstable();
 
-    Ok(Pages::Filesystem(pages.into()))
+    let pages = pages
+        .into_iter()
+        .map(|(_, path, size)| FilePage::new(path, size))
+        .collect();
+
+    Ok(Pages::Filesystem(pages))
 }
 
-fn load_pages_file(path: PathBuf) -> anyhow::Result<Pages> {
+fn load_pages_file(path: PathBuf, opts: &LoadOptions) -> anyhow::Result<Pages> {
     let file = File::open(&path)?;
     let ext = match path.extension() {
         Some(ext) => ext
@@ -162,44 +601,140 @@ fn load_pages_file(path: PathBuf) -> anyhow::Result<Pages> {
         _ => anyhow::bail!("unknown file type"),
     };
 
+    // only used by some archive formats
+    #[cfg(not(any(feature = "zip", feature = "7z", feature = "tar")))]
+    let _ = file;
+    #[cfg(not(any(
+        feature = "zip",
+        feature = "rar",
+        feature = "7z",
+        feature = "tar",
+        feature = "pdf"
+    )))]
+    let _ = opts;
+
     match ext {
         #[cfg(feature = "zip")]
-        "zip" | "cbz" => Ok(load_pages_zip(path, file).context("error reading zip")?),
+        "zip" | "cbz" => Ok(load_pages_zip(path, file, opts).context("error reading zip")?),
+        #[cfg(feature = "rar")]
+        "rar" | "cbr" => Ok(load_pages_rar(path, opts).context("error reading rar")?),
+        #[cfg(feature = "7z")]
+        "7z" | "cb7" => Ok(load_pages_7z(path, file, opts).context("error reading 7z")?),
+        #[cfg(feature = "tar")]
+        "tar" | "cbt" => Ok(load_pages_tar(path, file, false, opts).context("error reading tar")?),
+        #[cfg(feature = "tar")]
+        "tgz" => Ok(load_pages_tar(path, file, true, opts).context("error reading tar")?),
+        #[cfg(feature = "tar")]
+        "gz" if Path::new(path.file_stem().unwrap_or_default())
+            .extension()
+            .is_some_and(|v| v == "tar" || v == "cbt") =>
+        {
+            Ok(load_pages_tar(path, file, true, opts).context("error reading tar")?)
+        }
+        #[cfg(feature = "pdf")]
+        "pdf" => {
+            let pages = crate::pdf::load_pages(&path, opts).context("error reading pdf")?;
+            Ok(Pages::Pdf(path, pages))
+        }
         _ => anyhow::bail!("unknown file type: {:?}", ext),
     }
 }
 
 #[cfg(feature = "zip")]
-fn load_pages_zip(path: PathBuf, file: File) -> anyhow::Result<Pages> {
-    use std::ops::Deref;
+fn load_pages_zip(path: PathBuf, file: File, opts: &LoadOptions) -> anyhow::Result<Pages> {
+    use std::{io::Seek, ops::Deref};
 
+    use flate2::read::DeflateDecoder;
     use positioned_io::ReadAt;
-    use rc_zip::{reader::sync::ReadZip, EntryContents};
+    use rc_zip::{reader::sync::ReadZip, EntryContents, Method};
+
+    const LOCAL_HEADER_LEN: u64 = 30;
+    const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+
+    let file_len = file.metadata()?.len();
 
     let zip = ReadZip::read_zip(&file)?;
-    let mut entries = zip
+    let mut entries = Vec::new();
+    for entry in zip
         .deref()
         .entries()
         .filter(|entry| matches!(entry.contents(), EntryContents::File))
-        .map(|entry| {
-            let mut buf = [0; 4];
-            let sz = file.read_at(entry.header_offset + 26, &mut buf)?;
-            anyhow::ensure!(sz == 4, "read less than 4 bytes from zip");
-
-            let name_len = u16::from_le_bytes([buf[0], buf[1]]);
-            let extra_len = u16::from_le_bytes([buf[2], buf[3]]);
-
-            Ok((
-                entry.name(),
-                ZipEntry {
-                    method: entry.method(),
-                    data_offset: entry.header_offset + 30 + name_len as u64 + extra_len as u64,
-                    compressed_size: entry.compressed_size,
-                    uncompressed_size: entry.uncompressed_size,
-                },
-            ))
-        })
-        .collect::<Result<Vec<_>, _>>()?;
+    {
+        let res = (|| {
+            anyhow::ensure!(
+                entry
+                    .header_offset
+                    .checked_add(LOCAL_HEADER_LEN)
+                    .is_some_and(|end| end <= file_len),
+                "local header at {} is past the end of the file",
+                entry.header_offset
+            );
+
+            let mut buf = [0; LOCAL_HEADER_LEN as usize];
+            file.read_exact_at(entry.header_offset, &mut buf)?;
+
+            let signature = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+            anyhow::ensure!(
+                signature == LOCAL_HEADER_SIGNATURE,
+                "invalid local header signature"
+            );
+
+            let name_len = u16::from_le_bytes([buf[26], buf[27]]);
+            let extra_len = u16::from_le_bytes([buf[28], buf[29]]);
+            let data_offset =
+                entry.header_offset + LOCAL_HEADER_LEN + name_len as u64 + extra_len as u64;
+
+            anyhow::ensure!(
+                data_offset
+                    .checked_add(entry.compressed_size)
+                    .is_some_and(|end| end <= file_len),
+                "data ({} bytes at {}) overruns the file",
+                entry.compressed_size,
+                data_offset
+            );
+            anyhow::ensure!(
+                entry.uncompressed_size <= opts.max_page_size,
+                "uncompressed size of {} bytes is over the limit of {} bytes",
+                entry.uncompressed_size,
+                opts.max_page_size
+            );
+            if let Method::Store = entry.method() {
+                anyhow::ensure!(
+                    entry.compressed_size == entry.uncompressed_size,
+                    "stored entry has different compressed and uncompressed sizes"
+                );
+            }
+
+            let mime = page_mime(Path::new(entry.name()), || {
+                let mut magic = Vec::with_capacity(MAGIC_LEN);
+                let mut data = &file;
+                data.seek(io::SeekFrom::Start(data_offset))?;
+                let data = data.take(entry.compressed_size);
+                match entry.method() {
+                    Method::Store => data.take(MAGIC_LEN as u64).read_to_end(&mut magic)?,
+                    Method::Deflate => DeflateDecoder::new(data)
+                        .take(MAGIC_LEN as u64)
+                        .read_to_end(&mut magic)?,
+                    _ => 0,
+                };
+                Ok(magic)
+            });
+
+            Ok(ZipEntry {
+                method: entry.method(),
+                data_offset,
+                compressed_size: entry.compressed_size,
+                uncompressed_size: entry.uncompressed_size,
+                crc32: entry.crc32,
+                mime,
+            })
+        })();
+
+        match res {
+            Ok(page) => entries.push((entry.name(), page)),
+            Err(e) => error!("{:?}: skipping entry {:?}: {:#}", path, entry.name(), e),
+        }
+    }
 
     entries.sort_unstable_by_key(|(v, _)| *v);
 
@@ -208,37 +743,831 @@ fn load_pages_zip(path: PathBuf, file: File) -> anyhow::Result<Pages> {
     Ok(Pages::Zip(path, pages))
 }
 
-#[derive(Debug)]
+/// Indexes the files of a RAR archive.
+///
+/// Listing an archive only reads its headers, even for solid archives,
+/// so pages are only decompressed when they are served.
+#[cfg(feature = "rar")]
+fn load_pages_rar(path: PathBuf, opts: &LoadOptions) -> anyhow::Result<Pages> {
+    let archive = unrar::Archive::new(&path).open_for_listing()?;
+    let solid = archive.is_solid();
+
+    let mut entries = Vec::new();
+    for (index, header) in archive.enumerate() {
+        let header = header?;
+        if header.is_directory() {
+            continue;
+        }
+
+        let res = (|| {
+            anyhow::ensure!(!header.is_encrypted(), "entry is encrypted");
+            anyhow::ensure!(!header.is_split(), "entry is split across volumes");
+            anyhow::ensure!(
+                header.unpacked_size <= opts.max_page_size,
+                "uncompressed size of {} bytes is over the limit of {} bytes",
+                header.unpacked_size,
+                opts.max_page_size
+            );
+
+            // sniffing would mean decompressing the page, so only go by the name
+            let mime = page_mime(&header.filename, || Ok(Vec::new()));
+
+            Ok(RarEntry {
+                index,
+                size: header.unpacked_size,
+                crc32: header.file_crc,
+                mime,
+            })
+        })();
+
+        match res {
+            Ok(page) => entries.push((header.filename, page)),
+            Err(e) => error!("{:?}: skipping entry {:?}: {:#}", path, header.filename, e),
+        }
+    }
+
+    entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+    let pages = entries.into_iter().map(|(_, v)| v).collect();
+
+    Ok(Pages::Rar { path, solid, pages })
+}
+
+/// Extracts `pages` from the RAR archive at `path`, returning their contents in the same order.
+///
+/// Headers are walked from the start of the archive, and entries before the last page
+/// are skipped, which means decompressing them in solid archives.
+#[cfg(feature = "rar")]
+pub fn extract_rar(path: &Path, pages: &[RarEntry]) -> anyhow::Result<Vec<Vec<u8>>> {
+    let mut wanted = pages
+        .iter()
+        .enumerate()
+        .map(|(i, page)| (page.index, i))
+        .collect::<HashMap<_, _>>();
+    let mut extracted = vec![Vec::new(); pages.len()];
+
+    let mut archive = unrar::Archive::new(path).open_for_processing()?;
+    let mut index = 0;
+    while !wanted.is_empty() {
+        let Some(header) = archive.read_header()? else {
+            anyhow::bail!("{} pages are missing from the archive", wanted.len());
+        };
+
+        archive = match wanted.remove(&index) {
+            Some(i) => {
+                let page = &pages[i];
+                let (data, rest) = header.read()?;
+                anyhow::ensure!(
+                    data.len() as u64 == page.size,
+                    "entry #{} has a size of {} bytes, expected {}",
+                    index,
+                    data.len(),
+                    page.size
+                );
+                extracted[i] = data;
+                rest
+            }
+            None => header.skip()?,
+        };
+        index += 1;
+    }
+
+    Ok(extracted)
+}
+
+/// Indexes the files of a 7z archive.
+///
+/// Only the archive's header is read, pages are decompressed when they are served.
+#[cfg(feature = "7z")]
+fn load_pages_7z(path: PathBuf, mut file: File, opts: &LoadOptions) -> anyhow::Result<Pages> {
+    use sevenz_rust::{Archive, SevenZMethod};
+
+    let file_len = file.metadata()?.len();
+    let archive = Archive::read(&mut file, file_len, &[])?;
+
+    let mut solid = false;
+    let mut entries = Vec::new();
+    for (index, entry) in archive.files.iter().enumerate() {
+        if entry.is_directory() || entry.is_anti_item() {
+            continue;
+        }
+
+        let res = (|| {
+            let block = archive
+                .stream_map
+                .file_folder_index
+                .get(index)
+                .copied()
+                .flatten()
+                .context("entry is empty")?;
+            anyhow::ensure!(
+                !archive.folders[block]
+                    .coders
+                    .iter()
+                    .any(|v| v.decompression_method_id() == SevenZMethod::ID_AES256SHA256),
+                "entry is encrypted"
+            );
+            anyhow::ensure!(
+                entry.size() <= opts.max_page_size,
+                "uncompressed size of {} bytes is over the limit of {} bytes",
+                entry.size(),
+                opts.max_page_size
+            );
+
+            // sniffing would mean decompressing the page, so only go by the name
+            let mime = page_mime(Path::new(entry.name()), || Ok(Vec::new()));
+
+            Ok(SevenZEntry {
+                index,
+                block,
+                size: entry.size(),
+                mime,
+            })
+        })();
+
+        match res {
+            Ok(page) => {
+                solid |= archive.folders[page.block].num_unpack_sub_streams > 1;
+                entries.push((entry.name(), page));
+            }
+            Err(e) => error!("{:?}: skipping entry {:?}: {:#}", path, entry.name(), e),
+        }
+    }
+
+    entries.sort_unstable_by_key(|(v, _)| *v);
+
+    let pages = entries.into_iter().map(|(_, v)| v).collect();
+
+    Ok(Pages::SevenZ { path, solid, pages })
+}
+
+/// Extracts `pages` from the 7z archive at `path`, returning their contents in the same order.
+///
+/// Only the blocks holding the pages are decompressed, each from its start up to
+/// the last page needed from it.
+#[cfg(feature = "7z")]
+pub fn extract_7z(path: &Path, pages: &[SevenZEntry]) -> anyhow::Result<Vec<Vec<u8>>> {
+    use sevenz_rust::{Archive, BlockDecoder};
+
+    let mut file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let archive = Archive::read(&mut file, file_len, &[])?;
+
+    let mut wanted = pages
+        .iter()
+        .enumerate()
+        .map(|(i, page)| (page.index, i))
+        .collect::<HashMap<_, _>>();
+    let mut extracted = vec![Vec::new(); pages.len()];
+
+    let mut blocks = pages.iter().map(|v| v.block).collect::<Vec<_>>();
+    blocks.sort_unstable();
+    blocks.dedup();
+    for block in blocks {
+        anyhow::ensure!(
+            block < archive.folders.len(),
+            "block #{} is missing from the archive",
+            block
+        );
+
+        let mut index = archive.stream_map.folder_first_file_index[block];
+        let mut left = pages.iter().filter(|v| v.block == block).count();
+        BlockDecoder::new(block, &archive, &[], &mut file).for_each_entries(&mut |_, data| {
+            match wanted.remove(&index) {
+                Some(i) => {
+                    data.read_to_end(&mut extracted[i])?;
+                    left -= 1;
+                }
+                // entries share the block's stream, so skipped ones must still be read
+                None => {
+                    io::copy(data, &mut io::sink())?;
+                }
+            }
+            index += 1;
+            Ok(left > 0)
+        })?;
+    }
+    anyhow::ensure!(
+        wanted.is_empty(),
+        "{} pages are missing from the archive",
+        wanted.len()
+    );
+
+    for (page, data) in pages.iter().zip(&extracted) {
+        anyhow::ensure!(
+            data.len() as u64 == page.size,
+            "entry #{} has a size of {} bytes, expected {}",
+            page.index,
+            data.len(),
+            page.size
+        );
+    }
+
+    Ok(extracted)
+}
+
+/// Indexes the files of a tar archive, gzipped if `gzip` is set.
+///
+/// Plain archives are read in place, seeking over the pages,
+/// while gzipped ones are decompressed entirely to find their files.
+#[cfg(feature = "tar")]
+fn load_pages_tar(
+    path: PathBuf,
+    file: File,
+    gzip: bool,
+    opts: &LoadOptions,
+) -> anyhow::Result<Pages> {
+    use flate2::read::MultiGzDecoder;
+
+    let pages = if gzip {
+        let mut archive = tar::Archive::new(MultiGzDecoder::new(file));
+        index_tar(&path, archive.entries()?, None, opts)?
+    } else {
+        let file_len = file.metadata()?.len();
+        let mut archive = tar::Archive::new(file);
+        index_tar(&path, archive.entries_with_seek()?, Some(file_len), opts)?
+    };
+
+    Ok(Pages::Tar { path, gzip, pages })
+}
+
+/// Lists the regular files of a tar archive, sorted by name.
+///
+/// `file_len` is the length of the archive if pages are read from it in place.
+#[cfg(feature = "tar")]
+fn index_tar<R: Read>(
+    path: &Path,
+    entries: tar::Entries<'_, R>,
+    file_len: Option<u64>,
+    opts: &LoadOptions,
+) -> anyhow::Result<Box<[TarEntry]>> {
+    let mut pages = Vec::new();
+    for entry in entries {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.into_owned();
+
+        let res = (|| {
+            let data_offset = entry.raw_file_position();
+            let size = entry.size();
+            if let Some(file_len) = file_len {
+                anyhow::ensure!(
+                    data_offset
+                        .checked_add(size)
+                        .is_some_and(|end| end <= file_len),
+                    "data ({} bytes at {}) overruns the file",
+                    size,
+                    data_offset
+                );
+            }
+            anyhow::ensure!(
+                size <= opts.max_page_size,
+                "size of {} bytes is over the limit of {} bytes",
+                size,
+                opts.max_page_size
+            );
+
+            let mime = page_mime(&name, || {
+                let mut magic = Vec::with_capacity(MAGIC_LEN);
+                (&mut entry)
+                    .take(MAGIC_LEN as u64)
+                    .read_to_end(&mut magic)?;
+                Ok(magic)
+            });
+
+            Ok(TarEntry {
+                data_offset,
+                size,
+                mime,
+            })
+        })();
+
+        match res {
+            Ok(page) => pages.push((name, page)),
+            Err(e) => error!("{:?}: skipping entry {:?}: {:#}", path, name, e),
+        }
+    }
+
+    pages.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+    Ok(pages.into_iter().map(|(_, v)| v).collect())
+}
+
+/// Extracts `pages` from the gzipped tar archive at `path`, returning their contents in the same order.
+///
+/// The archive is decompressed from the start up to the last page.
+#[cfg(feature = "tar")]
+pub fn extract_tar_gz(path: &Path, pages: &[TarEntry]) -> anyhow::Result<Vec<Vec<u8>>> {
+    use flate2::read::MultiGzDecoder;
+
+    let mut wanted = pages
+        .iter()
+        .enumerate()
+        .map(|(i, page)| (page.data_offset, i))
+        .collect::<HashMap<_, _>>();
+    let mut extracted = vec![Vec::new(); pages.len()];
+
+    let mut archive = tar::Archive::new(MultiGzDecoder::new(File::open(path)?));
+    let mut entries = archive.entries()?;
+    while !wanted.is_empty() {
+        let Some(entry) = entries.next() else {
+            anyhow::bail!("{} pages are missing from the archive", wanted.len());
+        };
+        let mut entry = entry?;
+        if let Some(i) = wanted.remove(&entry.raw_file_position()) {
+            let page = &pages[i];
+            entry.read_to_end(&mut extracted[i])?;
+            anyhow::ensure!(
+                extracted[i].len() as u64 == page.size,
+                "entry at {} has a size of {} bytes, expected {}",
+                page.data_offset,
+                extracted[i].len(),
+                page.size
+            );
+        }
+    }
+
+    Ok(extracted)
+}
+
+/// Number of bytes read from the start of a page to sniff its type.
+const MAGIC_LEN: usize = 256;
+
+/// Determines the MIME type of a page from its file name,
+/// falling back to sniffing the start of its contents.
+fn page_mime(name: &Path, magic: impl FnOnce() -> io::Result<Vec<u8>>) -> &'static str {
+    const UNKNOWN: &str = "application/octet-stream";
+
+    let ext = name
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+
+    let mime = match ext.as_deref() {
+        Some("jpg" | "jpeg" | "jfif") => Some("image/jpeg"),
+        Some("png") => Some("image/png"),
+        Some("gif") => Some("image/gif"),
+        Some("webp") => Some("image/webp"),
+        Some("avif") => Some("image/avif"),
+        Some("jxl") => Some("image/jxl"),
+        Some("bmp") => Some("image/bmp"),
+        Some("tif" | "tiff") => Some("image/tiff"),
+        Some("heic") => Some("image/heic"),
+        Some("heif") => Some("image/heif"),
+        Some("svg") => Some("image/svg+xml"),
+        _ => None,
+    };
+    if let Some(mime) = mime {
+        return mime;
+    }
+
+    #[cfg(feature = "infer")]
+    {
+        match magic() {
+            Ok(magic) => infer::get(&magic).map_or(UNKNOWN, |v| v.mime_type()),
+            Err(e) => {
+                warn!("{:?}: error determining file type: {:#}", name, e);
+                UNKNOWN
+            }
+        }
+    }
+    #[cfg(not(feature = "infer"))]
+    {
+        let _ = magic;
+        warn!("{:?}: unknown file type", name);
+        UNKNOWN
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct LibraryEntry {
     pub json: JsonBytes,
-    pub mangas: HashMap<String, MangaEntry>,
+    /// The library listing with more information on each manga.
+    pub json_details: JsonBytes,
+    pub search: SearchIndex,
+    pub mangas: HashMap<String, Arc<MangaEntry>>,
+    /// Manga ids, in the order they are listed in the library.
+    pub order: Vec<String>,
+    /// The canonical library roots.
+    #[cfg(feature = "watch")]
+    pub roots: Vec<PathBuf>,
+    /// Number of roots and manga that failed to load.
+    pub errors: usize,
+    /// When the library was loaded or last changed.
+    pub modified: SystemTime,
+}
+
+impl LibraryEntry {
+    /// Reloads the manga in the directories `dirs` after the `changed` paths were modified,
+    /// adding, replacing or removing them as needed.
+    ///
+    /// Chapters without changes keep the pages they were loaded with.
+    /// The library listing is not updated, call [`LibraryEntry::rebuild_listing`] afterwards.
+    #[cfg(feature = "watch")]
+    pub fn reload_manga(
+        &mut self,
+        dirs: &BTreeSet<PathBuf>,
+        changed: &BTreeSet<PathBuf>,
+        opts: &LoadOptions,
+    ) {
+        // take all the reloaded manga out first, so one moving between directories
+        // isn't a duplicate of itself
+        let mut old = HashMap::new();
+        for dir in dirs {
+            let Some(id) = self
+                .mangas
+                .iter()
+                .find(|(_, manga)| manga.path == *dir)
+                .map(|(id, _)| id.clone())
+            else {
+                continue;
+            };
+            let manga = self.mangas.remove(&id).expect("id was just found");
+            let pos = self.order.iter().position(|v| *v == id);
+            old.insert(dir, (id, pos, manga));
+        }
+        // positions in `order` of removed manga that weren't added back
+        let mut freed = old
+            .values()
+            .filter_map(|(_, pos, _)| *pos)
+            .collect::<BTreeSet<_>>();
+
+        let mut read_buf = Vec::new();
+        for dir in dirs {
+            let old = old.remove(dir);
+            let loaded = old
+                .iter()
+                .flat_map(|(.., manga)| manga.chapters.iter())
+                .filter(|ch| {
+                    !changed
+                        .iter()
+                        .any(|path| path.starts_with(&ch.path) || ch.path.starts_with(path))
+                })
+                .map(|ch| (ch.path.clone(), ch.pages.clone()))
+                .collect();
+
+            let (id, manga) =
+                match read_manga(dir.clone(), &mut read_buf, &self.roots, &loaded, opts) {
+                    None => {
+                        if let Some((old, ..)) = old {
+                            info!("{:?}: removed manga {:?}", dir, old);
+                        }
+                        continue;
+                    }
+                    Some(Ok((id, manga))) => (id, Arc::new(manga)),
+                    Some(Err(e)) => {
+                        error!("{:#}", e);
+                        // keep serving what was there before
+                        if let Some((id, pos, manga)) = old {
+                            self.insert(id, pos, manga, &mut freed, opts);
+                        }
+                        continue;
+                    }
+                };
+
+            let (old_id, pos) = match &old {
+                Some((id, pos, _)) => (Some(id), *pos),
+                None => (None, None),
+            };
+            match self.insert(id.clone(), pos, manga, &mut freed, opts) {
+                Some(new) => match old_id {
+                    Some(old) if *old == new => info!("{:?}: reloaded manga {:?}", dir, new),
+                    Some(old) => info!("{:?}: reloaded manga {:?} as {:?}", dir, old, new),
+                    None => info!("{:?}: added manga {:?}", dir, new),
+                },
+                None => {
+                    if let Some(old) = old_id {
+                        info!("{:?}: removed manga {:?}", dir, old);
+                    }
+                }
+            }
+        }
+
+        let mut pos = 0;
+        self.order.retain(|_| {
+            pos += 1;
+            !freed.contains(&(pos - 1))
+        });
+    }
+
+    /// Adds a reloaded manga, at the position `pos` in the library order if it had one.
+    ///
+    /// Returns the id it was added under, or `None` if it was left out.
+    #[cfg(feature = "watch")]
+    fn insert(
+        &mut self,
+        id: String,
+        pos: Option<usize>,
+        manga: Arc<MangaEntry>,
+        freed: &mut BTreeSet<usize>,
+        opts: &LoadOptions,
+    ) -> Option<String> {
+        let path = manga.path.clone();
+        match insert_manga(&mut self.mangas, id.clone(), manga, opts) {
+            Ok(Inserted::New(id)) => {
+                match pos {
+                    Some(pos) => {
+                        freed.remove(&pos);
+                        self.order[pos] = id.clone();
+                    }
+                    None => self.order.push(id.clone()),
+                }
+                Some(id)
+            }
+            Ok(Inserted::Replaced) => Some(id),
+            Ok(Inserted::Skipped) => None,
+            Err(e) => {
+                error!("{:#}, leaving {:?} out", e, path);
+                None
+            }
+        }
+    }
+
+    /// Regenerates the library listing and search index after manga were added or removed.
+    #[cfg(feature = "watch")]
+    pub fn rebuild_listing(&mut self) -> anyhow::Result<()> {
+        self.json = library_json(&self.order, &self.mangas, false)?;
+        self.json_details = library_json(&self.order, &self.mangas, true)?;
+        self.search = SearchIndex::new(self.order.iter().map(|id| &*self.mangas[id]));
+        self.modified = SystemTime::now();
+        Ok(())
+    }
+
+    /// Serializes one page of a listing of the manga at the given positions in the library order.
+    pub fn listing_page(
+        &self,
+        docs: &[u32],
+        has_next_page: bool,
+        details: bool,
+    ) -> anyhow::Result<JsonBytes> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct ListingPage<'a> {
+            mangas: Vec<LibraryEntrySer<'a>>,
+            has_next_page: bool,
+        }
+
+        let mangas = docs
+            .iter()
+            .map(|&doc| {
+                let id = &self.order[doc as usize];
+                LibraryEntrySer {
+                    id,
+                    manga: &self.mangas[id],
+                    details,
+                }
+            })
+            .collect();
+
+        let json = serde_json::to_vec(&ListingPage {
+            mangas,
+            has_next_page,
+        })?;
+        Ok(JsonBytes::dynamic(json.into(), self.modified))
+    }
+}
+
+/// The library being served, which can be replaced while serving.
+///
+/// Requests keep using the library they started with,
+/// so swapping it out never interrupts them.
+#[derive(Debug)]
+pub struct Library {
+    current: RwLock<Arc<LibraryEntry>>,
+    update: Mutex<()>,
+    paths: Vec<PathBuf>,
+    opts: LoadOptions,
+}
+
+impl Library {
+    /// Loads the library from `paths`, which are kept for later rescans.
+    pub fn load(paths: Vec<PathBuf>, opts: LoadOptions) -> anyhow::Result<Self> {
+        Ok(Self {
+            current: RwLock::new(Arc::new(load_library(&paths, &opts)?)),
+            update: Mutex::new(()),
+            paths,
+            opts,
+        })
+    }
+
+    #[cfg(feature = "watch")]
+    pub fn opts(&self) -> &LoadOptions {
+        &self.opts
+    }
+
+    /// Returns the library currently being served.
+    pub fn get(&self) -> Arc<LibraryEntry> {
+        self.current
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Replaces the library with the result of `f`, or keeps it if `f` returns `None`.
+    ///
+    /// Updates are serialized, and the old library keeps being served while `f` runs.
+    pub fn update(&self, f: impl FnOnce(&LibraryEntry) -> anyhow::Result<Option<LibraryEntry>>) {
+        let _guard = self.update.lock().unwrap_or_else(PoisonError::into_inner);
+
+        match f(&self.get()) {
+            Ok(None) => {}
+            Ok(Some(lib)) => {
+                *self.current.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(lib);
+            }
+            Err(e) => error!("error updating library: {:#}", e),
+        }
+    }
+
+    /// Loads the whole library again and swaps it in, logging what changed.
+    ///
+    /// The old library is kept if loading fails.
+    pub fn rescan(&self) {
+        info!("rescanning library");
+        self.update(|old| {
+            let new = load_library(&self.paths, &self.opts)?;
+
+            let added = new
+                .order
+                .iter()
+                .filter(|id| !old.mangas.contains_key(*id))
+                .count();
+            let removed = old
+                .order
+                .iter()
+                .filter(|id| !new.mangas.contains_key(*id))
+                .count();
+            let changed = new
+                .mangas
+                .iter()
+                .filter(|(id, manga)| {
+                    old.mangas.get(*id).is_some_and(|old| {
+                        old.path != manga.path || old.json.as_bytes() != manga.json.as_bytes()
+                    })
+                })
+                .count();
+
+            info!(
+                "rescan finished: {} added, {} removed, {} changed, {} errors",
+                added, removed, changed, new.errors
+            );
+            Ok(Some(new))
+        });
+    }
 }
 
 #[derive(Debug)]
 pub struct MangaEntry {
+    /// The manga directory.
+    pub path: PathBuf,
+    pub title: String,
+    /// Comma separated author names.
+    pub authors: String,
+    /// Comma separated tags.
+    pub tags: String,
+    pub description: String,
+    pub status: MangaStatus,
+    /// The text fields normalized for filtering, see [`normalize`].
+    pub normalized: NormalizedText,
+    /// When the manga directory was created, in milliseconds since the Unix epoch.
+    pub added: u64,
+    /// The date of the newest chapter, using the modification time
+    /// of the chapter files for chapters without a date.
+    pub latest_chapter: u64,
     pub json: JsonBytes,
-    pub cover: Option<Cover>,
+    pub cover: Option<CoverEntry>,
     pub chapters: Box<[ChapterEntry]>,
 }
 
 impl MangaEntry {
-    fn new(manga: Manga) -> anyhow::Result<Self> {
+    fn new(path: PathBuf, manga: Manga) -> anyhow::Result<Self> {
+        let added = fs::metadata(&path)
+            .and_then(|v| v.created().or_else(|_| v.modified()))
+            .map_or(0, unix_millis);
+
+        let normalized = NormalizedText {
+            title: normalize(&manga.title),
+            authors: normalize(&manga.authors.0),
+            artists: normalize(&manga.artists.0),
+            tags: normalize_list(&manga.tags.0),
+        };
+
         Ok(Self {
+            added,
+            normalized,
+            latest_chapter: manga
+                .chapters
+                .iter()
+                .map(|ch| match ch.date {
+                    0 => fs::metadata(&ch.path)
+                        .and_then(|v| v.modified())
+                        .map_or(0, unix_millis),
+                    date => date,
+                })
+                .max()
+                .unwrap_or(0),
+            status: manga.status,
+            path,
             json: serde_json::to_vec(&manga)?.into(),
+            title: manga.title.into_owned(),
+            authors: manga.authors.0.into_owned(),
+            tags: manga.tags.0.into_owned(),
+            description: manga.description,
             cover: manga.cover.map(Into::into),
             chapters: manga.chapters.into_iter().map(ChapterEntry::new).collect(),
         })
     }
 }
 
+/// The text of a manga lowercased and without accents, computed once when loading.
+#[derive(Debug)]
+pub struct NormalizedText {
+    pub title: String,
+    pub authors: String,
+    pub artists: String,
+    pub tags: Vec<String>,
+}
+
 #[derive(Debug)]
 pub struct ChapterEntry {
+    /// The chapter directory or archive.
+    #[cfg(feature = "watch")]
+    pub path: PathBuf,
+    pub title: String,
+    pub date: u64,
     pub pages: Pages,
 }
 
 impl ChapterEntry {
     fn new(ch: Chapter) -> Self {
-        Self { pages: ch.pages }
+        Self {
+            #[cfg(feature = "watch")]
+            path: ch.path.into_owned(),
+            title: ch.title.into_owned(),
+            date: ch.date,
+            pages: ch.pages,
+        }
+    }
+
+    /// Serializes the chapter `ch` of the manga `id`, with the URL, type and size of each page.
+    ///
+    /// `dimensions` are the width and height of each page, if known.
+    pub fn detail_json(
+        &self,
+        id: &str,
+        ch: usize,
+        dimensions: Option<&[Option<(usize, usize)>]>,
+    ) -> anyhow::Result<JsonBytes> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct ChapterSer<'a> {
+            title: &'a str,
+            #[serde(skip_serializing_if = "is_zero")]
+            date: u64,
+            page_count: u32,
+            pages: Vec<PageSer>,
+        }
+
+        #[derive(Serialize)]
+        struct PageSer {
+            url: String,
+            mime: &'static str,
+            /// Left out for pages converted when they are served.
+            #[serde(skip_serializing_if = "Option::is_none")]
+            size: Option<u64>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            width: Option<usize>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            height: Option<usize>,
+        }
+
+        let pages = self
+            .pages
+            .info()
+            .into_iter()
+            .enumerate()
+            .map(|(pg, (mime, size))| {
+                let (width, height) = dimensions
+                    .and_then(|v| v.get(pg).copied().flatten())
+                    .unzip();
+                PageSer {
+                    url: format!("{}/{}/{}", manga_url(id), ch, pg),
+                    mime,
+                    size,
+                    width,
+                    height,
+                }
+            })
+            .collect();
+
+        Ok(serde_json::to_vec(&ChapterSer {
+            title: &self.title,
+            date: self.date,
+            page_count: self.pages.len(),
+            pages,
+        })?
+        .into())
     }
 }
 
@@ -335,9 +1664,10 @@ impl<'de: 'a, 'a> Deserialize<'de> for TachiyomiList<'a> {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase", into = "u32")]
 pub enum MangaStatus {
+    #[default]
     Unknown = 0,
     Ongoing = 1,
     Completed = 2,
@@ -357,9 +1687,21 @@ impl MangaStatus {
     }
 }
 
-impl Default for MangaStatus {
-    fn default() -> Self {
-        Self::Unknown
+impl FromStr for MangaStatus {
+    type Err = String;
+
+    /// Parses a status by name, as in `info.toml`, or by number.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unknown" | "0" => Ok(Self::Unknown),
+            "ongoing" | "1" => Ok(Self::Ongoing),
+            "completed" | "2" => Ok(Self::Completed),
+            "licensed" | "3" => Ok(Self::Licensed),
+            "publishingfinished" | "4" => Ok(Self::PublishingFinished),
+            "cancelled" | "5" => Ok(Self::Cancelled),
+            "onhiatus" | "6" => Ok(Self::OnHiatus),
+            _ => Err(format!("unknown manga status {:?}", s)),
+        }
     }
 }
 
@@ -369,6 +1711,11 @@ impl From<MangaStatus> for u32 {
     }
 }
 
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |v| v.as_millis().try_into().unwrap_or(u64::MAX))
+}
+
 fn is_zero(&v: &u64) -> bool {
     v == 0
 }
@@ -386,17 +1733,61 @@ pub enum Cover {
 }
 
 #[derive(Debug)]
+pub enum CoverEntry {
+    File(FilePage),
+    Page { ch: usize, pg: usize },
+}
+
+impl From<Cover> for CoverEntry {
+    fn from(v: Cover) -> Self {
+        match v {
+            Cover::File(path) => {
+                let size = fs::metadata(&path).map_or_else(
+                    |e| {
+                        warn!("{:?}: error reading cover: {}", path, e);
+                        0
+                    },
+                    |v| v.len(),
+                );
+                Self::File(FilePage::new(path, size))
+            }
+            Cover::Page { ch, pg } => Self::Page { ch, pg },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub enum Pages {
+    #[default]
     None,
-    Filesystem(Box<[PathBuf]>),
+    Filesystem(Box<[FilePage]>),
     #[cfg(feature = "zip")]
     Zip(PathBuf, Box<[ZipEntry]>),
-}
-
-impl Default for Pages {
-    fn default() -> Self {
-        Self::None
-    }
+    #[cfg(feature = "rar")]
+    Rar {
+        path: PathBuf,
+        /// Whether the archive is compressed as a single stream,
+        /// so pages can't be extracted without the ones before them.
+        solid: bool,
+        pages: Box<[RarEntry]>,
+    },
+    #[cfg(feature = "7z")]
+    SevenZ {
+        path: PathBuf,
+        /// Whether some pages share a compressed block with other files,
+        /// so they can't be extracted without the ones before them.
+        solid: bool,
+        pages: Box<[SevenZEntry]>,
+    },
+    #[cfg(feature = "tar")]
+    Tar {
+        path: PathBuf,
+        /// Whether the archive is gzipped, so pages can't be read in place.
+        gzip: bool,
+        pages: Box<[TarEntry]>,
+    },
+    #[cfg(feature = "pdf")]
+    Pdf(PathBuf, Box<[PdfPage]>),
 }
 
 impl Pages {
@@ -404,11 +1795,120 @@ impl Pages {
         match self {
             Pages::None => 0,
             Pages::Filesystem(v) => v.len(),
+            #[cfg(feature = "zip")]
             Pages::Zip(.., v) => v.len(),
+            #[cfg(feature = "rar")]
+            Pages::Rar { pages, .. } => pages.len(),
+            #[cfg(feature = "7z")]
+            Pages::SevenZ { pages, .. } => pages.len(),
+            #[cfg(feature = "tar")]
+            Pages::Tar { pages, .. } => pages.len(),
+            #[cfg(feature = "pdf")]
+            Pages::Pdf(.., v) => v.len(),
         }
         .try_into()
         .expect("over u32::MAX (4,294,967,295) pages")
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The MIME type and size of each page, the size being `None` if it is not known before serving it.
+    pub fn info(&self) -> Vec<(&'static str, Option<u64>)> {
+        match self {
+            Pages::None => Vec::new(),
+            Pages::Filesystem(v) => v.iter().map(|v| (v.mime, Some(v.size))).collect(),
+            #[cfg(feature = "zip")]
+            Pages::Zip(.., v) => v
+                .iter()
+                .map(|v| (v.mime, Some(v.uncompressed_size)))
+                .collect(),
+            #[cfg(feature = "rar")]
+            Pages::Rar { pages, .. } => pages.iter().map(|v| (v.mime, Some(v.size))).collect(),
+            #[cfg(feature = "7z")]
+            Pages::SevenZ { pages, .. } => pages.iter().map(|v| (v.mime, Some(v.size))).collect(),
+            #[cfg(feature = "tar")]
+            Pages::Tar { pages, .. } => pages.iter().map(|v| (v.mime, Some(v.size))).collect(),
+            #[cfg(feature = "pdf")]
+            Pages::Pdf(.., v) => v.iter().map(|v| (v.mime(), v.size())).collect(),
+        }
+    }
+
+    /// Reads up to `len` bytes from the start of page `pg`, e.g. to parse its header.
+    pub fn read_start(&self, pg: usize, len: u64) -> anyhow::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        match self {
+            Pages::None => anyhow::bail!("page #{} does not exist", pg),
+            Pages::Filesystem(v) => {
+                let page = v.get(pg).context("page does not exist")?;
+                File::open(&page.path)?.take(len).read_to_end(&mut buf)?;
+            }
+            #[cfg(feature = "zip")]
+            Pages::Zip(path, v) => {
+                use std::io::Seek;
+
+                use flate2::read::DeflateDecoder;
+                use rc_zip::Method;
+
+                let page = v.get(pg).context("page does not exist")?;
+                let mut file = File::open(path)?;
+                file.seek(io::SeekFrom::Start(page.data_offset))?;
+                let data = file.take(page.compressed_size);
+                match page.method {
+                    Method::Store => data.take(len).read_to_end(&mut buf)?,
+                    Method::Deflate => DeflateDecoder::new(data).take(len).read_to_end(&mut buf)?,
+                    _ => anyhow::bail!("unsupported compression method"),
+                };
+            }
+            #[cfg(feature = "rar")]
+            Pages::Rar { path, pages, .. } => {
+                let page = pages.get(pg).context("page does not exist")?;
+                let mut data = extract_rar(path, slice::from_ref(page))?.remove(0);
+                data.truncate(len.try_into().unwrap_or(usize::MAX));
+                buf = data;
+            }
+            #[cfg(feature = "7z")]
+            Pages::SevenZ { path, pages, .. } => {
+                let page = pages.get(pg).context("page does not exist")?;
+                let mut data = extract_7z(path, slice::from_ref(page))?.remove(0);
+                data.truncate(len.try_into().unwrap_or(usize::MAX));
+                buf = data;
+            }
+            #[cfg(feature = "tar")]
+            Pages::Tar {
+                path,
+                gzip: false,
+                pages,
+            } => {
+                use std::io::Seek;
+
+                let page = pages.get(pg).context("page does not exist")?;
+                let mut file = File::open(path)?;
+                file.seek(io::SeekFrom::Start(page.data_offset))?;
+                file.take(page.size.min(len)).read_to_end(&mut buf)?;
+            }
+            #[cfg(feature = "tar")]
+            Pages::Tar {
+                path,
+                gzip: true,
+                pages,
+            } => {
+                let page = pages.get(pg).context("page does not exist")?;
+                let mut data = extract_tar_gz(path, slice::from_ref(page))?.remove(0);
+                data.truncate(len.try_into().unwrap_or(usize::MAX));
+                buf = data;
+            }
+            #[cfg(feature = "pdf")]
+            Pages::Pdf(path, v) => {
+                let page = v.get(pg).context("page does not exist")?;
+                let mut data = crate::pdf::read_page(path, page)?;
+                data.truncate(len.try_into().unwrap_or(usize::MAX));
+                buf = data;
+            }
+        }
+        Ok(buf)
+    }
 }
 
 impl Serialize for Pages {
@@ -424,4 +1924,142 @@ pub struct ZipEntry {
     pub data_offset: u64,
     pub compressed_size: u64,
     pub uncompressed_size: u64,
+    pub crc32: u32,
+    pub mime: &'static str,
+}
+
+/// A page of an archive that is extracted to be served.
+#[cfg(any(feature = "rar", feature = "7z", feature = "tar"))]
+pub trait ArchiveEntry: std::hash::Hash {
+    fn size(&self) -> u64;
+    fn mime(&self) -> &'static str;
+}
+
+/// A file in a RAR archive.
+#[cfg(feature = "rar")]
+#[derive(Debug, Clone, Copy, Hash)]
+pub struct RarEntry {
+    /// Position of the entry's header in the archive, counting directories.
+    pub index: usize,
+    pub size: u64,
+    pub crc32: u32,
+    pub mime: &'static str,
+}
+
+/// A file in a 7z archive.
+#[cfg(feature = "7z")]
+#[derive(Debug, Clone, Copy, Hash)]
+pub struct SevenZEntry {
+    /// Position of the entry in the archive, counting directories.
+    pub index: usize,
+    /// The compressed block holding the entry.
+    pub block: usize,
+    pub size: u64,
+    pub mime: &'static str,
+}
+
+/// A regular file in a tar archive.
+#[cfg(feature = "tar")]
+#[derive(Debug, Clone, Copy, Hash)]
+pub struct TarEntry {
+    /// Offset of the file's data, in the decompressed archive if it is gzipped.
+    pub data_offset: u64,
+    pub size: u64,
+    pub mime: &'static str,
+}
+
+#[cfg(feature = "rar")]
+impl ArchiveEntry for RarEntry {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn mime(&self) -> &'static str {
+        self.mime
+    }
+}
+
+#[cfg(feature = "7z")]
+impl ArchiveEntry for SevenZEntry {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn mime(&self) -> &'static str {
+        self.mime
+    }
+}
+
+#[cfg(feature = "tar")]
+impl ArchiveEntry for TarEntry {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn mime(&self) -> &'static str {
+        self.mime
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FilePage {
+    pub path: PathBuf,
+    pub mime: &'static str,
+    /// Size of the file when it was loaded.
+    pub size: u64,
+}
+
+impl FilePage {
+    fn new(path: PathBuf, size: u64) -> Self {
+        let mime = page_mime(&path, || {
+            let mut magic = Vec::with_capacity(MAGIC_LEN);
+            File::open(&path)?
+                .take(MAGIC_LEN as u64)
+                .read_to_end(&mut magic)?;
+            Ok(magic)
+        });
+
+        Self { path, mime, size }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mime_from_extension() {
+        let unread = || -> io::Result<Vec<u8>> { panic!("the extension is enough") };
+        assert_eq!(page_mime(Path::new("01.jpg"), unread), "image/jpeg");
+        assert_eq!(page_mime(Path::new("ch/01.JPEG"), unread), "image/jpeg");
+        assert_eq!(page_mime(Path::new("01.webp"), unread), "image/webp");
+    }
+
+    #[cfg(feature = "infer")]
+    #[test]
+    fn mime_from_magic() {
+        const PNG_MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR";
+        assert_eq!(
+            page_mime(Path::new("01"), || Ok(PNG_MAGIC.to_vec())),
+            "image/png"
+        );
+        assert_eq!(
+            page_mime(Path::new("01.dat"), || Ok(PNG_MAGIC.to_vec())),
+            "image/png"
+        );
+    }
+
+    #[test]
+    fn unknown_mime() {
+        let unknown = "application/octet-stream";
+        assert_eq!(
+            page_mime(Path::new("01.txt"), || Ok(b"hello".to_vec())),
+            unknown
+        );
+        assert_eq!(page_mime(Path::new("01"), || Ok(Vec::new())), unknown);
+        assert_eq!(
+            page_mime(Path::new("01"), || Err(io::ErrorKind::NotFound.into())),
+            unknown
+        );
+    }
 }
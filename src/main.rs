@@ -1,16 +1,27 @@
 #![warn(clippy::all)]
 
+use std::sync::Arc;
+
 use log::error;
 
 mod args;
+mod config;
 mod load;
+#[cfg(feature = "pdf")]
+mod pdf;
+mod search;
 mod server;
+#[cfg(feature = "watch")]
+mod watch;
 
 use args::Args;
-use load::load_library;
-use server::ServerBuilder;
+use load::Library;
+use server::{Auth, ServerBuilder};
 
 fn main() {
+    // before anything gets a chance to spawn a thread
+    #[cfg(unix)]
+    server::take_listen_fds();
     simple_logger::init_with_level(log::Level::Info).unwrap();
 
     if let Err(e) = try_main() {
@@ -20,9 +31,46 @@ fn main() {
 }
 
 fn try_main() -> anyhow::Result<()> {
-    let Some(Args { port, path }) = Args::parse()? else { return Ok(()) };
+    let Some(Args {
+        listen,
+        paths,
+        cache,
+        threads,
+        watch,
+        admin_token,
+        auth,
+        tls,
+        extract_cache_size,
+        load,
+    }) = Args::parse()? else { return Ok(()) };
+
+    let auth = auth.as_deref().map(Auth::load).transpose()?;
+    let lib = Arc::new(Library::load(paths, load)?);
+
+    if watch {
+        #[cfg(feature = "watch")]
+        watch::spawn(lib.clone())?;
+        #[cfg(not(feature = "watch"))]
+        anyhow::bail!("--watch is not supported, rebuild with the \"watch\" feature");
+    }
 
-    let lib = load_library(&[&path])?;
+    let server = ServerBuilder::new(listen)
+        .cache_policy(cache)
+        .threads(threads)
+        .admin_token(admin_token)
+        .auth(auth);
+
+    #[cfg(any(feature = "rar", feature = "7z", feature = "tar", feature = "pdf"))]
+    let server = server.extract_cache_size(extract_cache_size);
+    #[cfg(not(any(feature = "rar", feature = "7z", feature = "tar", feature = "pdf")))]
+    let _ = extract_cache_size;
+
+    #[cfg(feature = "tls")]
+    let server = server.tls(tls.map(|(cert, key)| server::TlsConfig { cert, key }));
+    #[cfg(not(feature = "tls"))]
+    if tls.is_some() {
+        anyhow::bail!("--tls-cert is not supported, rebuild with the \"tls\" feature");
+    }
 
-    ServerBuilder::new(port).run(lib)
+    server.run(lib)
 }
@@ -0,0 +1,644 @@
+use std::{
+    env,
+    fs::File,
+    io::{self, Read, Write},
+    path::Path,
+    sync::OnceLock,
+};
+
+use anyhow::Context;
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression, Crc};
+use log::{debug, error};
+use lopdf::{xref::XrefEntry, Dictionary, Document, Object, ObjectId, Stream};
+use pdfium_render::prelude::{PdfRenderConfig, Pdfium};
+use positioned_io::ReadAt;
+
+use crate::load::LoadOptions;
+
+/// Resolution of rasterized pages, PDF units being 1/72 inch.
+const RENDER_DPI: f32 = 300.0;
+/// Maximum width and height of rasterized pages, in pixels.
+const MAX_RENDER_SIZE: f32 = 8192.0;
+/// How far up the page tree inherited attributes are looked for.
+const MAX_TREE_DEPTH: usize = 32;
+
+/// Size of a PNG file besides its image data: signature, `IHDR`, and the headers
+/// and checksums of the `IDAT` and `IEND` chunks.
+const PNG_OVERHEAD: u64 = 8 + (12 + 13) + 12 + 12;
+const PNG_GRAYSCALE: u8 = 0;
+const PNG_RGB: u8 = 2;
+const PNG_RGBA: u8 = 6;
+
+/// The signature box starting JPEG 2000 files, as opposed to bare codestreams.
+const JP2_SIGNATURE: &[u8] = b"\0\0\0\x0cjP  \r\n\x87\n";
+
+/// A page of a PDF file.
+#[derive(Debug, Clone, Copy)]
+pub struct PdfPage {
+    /// Position of the page in the document.
+    pub index: u16,
+    /// Size of the served image, in pixels.
+    pub width: u32,
+    pub height: u32,
+    /// The image making up the whole page, or `None` if the page has to be rasterized.
+    pub image: Option<PdfImage>,
+}
+
+/// An image stream stored in a PDF file.
+#[derive(Debug, Clone, Copy)]
+pub struct PdfImage {
+    /// Offset of the stream's data in the file.
+    pub data_offset: u64,
+    pub len: u64,
+    pub encoding: ImageEncoding,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageEncoding {
+    /// A JPEG file, from a `DCTDecode` stream.
+    Jpeg,
+    /// A JPEG 2000 file, from a `JPXDecode` stream.
+    Jp2,
+    /// A bare JPEG 2000 codestream, from a `JPXDecode` stream.
+    J2k,
+    /// Zlib compressed pixels from a `FlateDecode` stream, served as PNG.
+    Flate {
+        color_type: u8,
+        bit_depth: u8,
+        /// Whether rows start with a PNG filter type, so the stream is valid PNG data as is.
+        predicted: bool,
+    },
+}
+
+impl PdfPage {
+    pub fn mime(&self) -> &'static str {
+        match self.image.map(|v| v.encoding) {
+            Some(ImageEncoding::Jpeg) => "image/jpeg",
+            Some(ImageEncoding::Jp2) => "image/jp2",
+            Some(ImageEncoding::J2k) => "image/j2k",
+            _ => "image/png",
+        }
+    }
+
+    /// Size of the served image in bytes, or `None` if it is only known once the page is converted.
+    pub fn size(&self) -> Option<u64> {
+        match self.image {
+            Some(PdfImage {
+                encoding: ImageEncoding::Jpeg | ImageEncoding::Jp2 | ImageEncoding::J2k,
+                len,
+                ..
+            }) => Some(len),
+            Some(PdfImage {
+                encoding:
+                    ImageEncoding::Flate {
+                        predicted: true, ..
+                    },
+                len,
+                ..
+            }) => Some(len + PNG_OVERHEAD),
+            _ => None,
+        }
+    }
+
+    /// Size of the page's pixels, which bounds the PNG it is converted to but for
+    /// the filter byte starting each row.
+    pub fn pixels_size(&self) -> u64 {
+        match self.image {
+            Some(PdfImage {
+                encoding:
+                    ImageEncoding::Flate {
+                        color_type,
+                        bit_depth,
+                        ..
+                    },
+                ..
+            }) => (row_len(self.width, color_type, bit_depth) + 1) * self.height as u64,
+            _ => (row_len(self.width, PNG_RGBA, 8) + 1) * self.height as u64,
+        }
+    }
+
+    /// Returns the image stream if it can be served from the file without conversion.
+    pub fn stored_image(&self) -> Option<&PdfImage> {
+        self.image.as_ref().filter(|v| {
+            matches!(
+                v.encoding,
+                ImageEncoding::Jpeg | ImageEncoding::Jp2 | ImageEncoding::J2k
+            )
+        })
+    }
+}
+
+/// Lists the pages of a PDF file, finding the ones that are a single image.
+pub fn load_pages(path: &Path, opts: &LoadOptions) -> anyhow::Result<Box<[PdfPage]>> {
+    let file = File::open(path)?;
+    let doc = Document::load_filtered(path, strip_image_data)?;
+
+    let mut pages = Vec::new();
+    for (number, page_id) in doc.get_pages() {
+        let res = (|| {
+            let index = u16::try_from(number - 1).context("too many pages")?;
+
+            let image = page_image(&file, &doc, page_id).unwrap_or_else(|e| {
+                debug!("{:?}: rasterizing page {}: {:#}", path, number, e);
+                None
+            });
+            let (width, height) = match image {
+                Some((_, width, height)) => (width, height),
+                None => render_size(&doc, page_id)?,
+            };
+            let page = PdfPage {
+                index,
+                width,
+                height,
+                image: image.map(|(v, ..)| v),
+            };
+
+            // converted pages are held in memory whole
+            let size = match page.stored_image() {
+                Some(image) => image.len,
+                None => page.pixels_size(),
+            };
+            anyhow::ensure!(
+                size <= opts.max_page_size,
+                "image size of {} bytes is over the limit of {} bytes",
+                size,
+                opts.max_page_size
+            );
+
+            Ok(page)
+        })();
+
+        match res {
+            Ok(page) => pages.push(page),
+            Err(e) => error!("{:?}: skipping page {}: {:#}", path, number, e),
+        }
+    }
+
+    Ok(pages.into())
+}
+
+/// Returns the contents of a page as an image file,
+/// converting or rasterizing it if it can't be served from the file as is.
+pub fn read_page(path: &Path, page: &PdfPage) -> anyhow::Result<Vec<u8>> {
+    let Some(image) = &page.image else {
+        return render_page(path, page);
+    };
+
+    let file = File::open(path)?;
+    let mut data = vec![0; image.len.try_into()?];
+    file.read_exact_at(image.data_offset, &mut data)?;
+
+    match image.encoding {
+        ImageEncoding::Jpeg | ImageEncoding::Jp2 | ImageEncoding::J2k => Ok(data),
+        ImageEncoding::Flate {
+            color_type,
+            bit_depth,
+            predicted: true,
+        } => Ok(png(page.width, page.height, color_type, bit_depth, &data)),
+        ImageEncoding::Flate {
+            color_type,
+            bit_depth,
+            predicted: false,
+        } => {
+            let row_len = row_len(page.width, color_type, bit_depth);
+            let len = row_len * page.height as u64;
+            let mut pixels = Vec::new();
+            ZlibDecoder::new(&*data)
+                .take(len)
+                .read_to_end(&mut pixels)?;
+            anyhow::ensure!(
+                pixels.len() as u64 == len,
+                "image data is {} bytes, expected {}",
+                pixels.len(),
+                len
+            );
+            let idat = filter_rows(&pixels, row_len as usize)?;
+            Ok(png(page.width, page.height, color_type, bit_depth, &idat))
+        }
+    }
+}
+
+/// Rasterizes a page to PNG.
+fn render_page(path: &Path, page: &PdfPage) -> anyhow::Result<Vec<u8>> {
+    let doc = pdfium()?.load_pdf_from_file(path, None)?;
+    let pdf_page = doc.pages().get(page.index)?;
+    let bitmap = pdf_page.render_with_config(
+        &PdfRenderConfig::new()
+            .set_fixed_width(page.width.try_into()?)
+            .set_fixed_height(page.height.try_into()?),
+    )?;
+    let (width, height) = (bitmap.width().try_into()?, bitmap.height().try_into()?);
+    let idat = filter_rows(&bitmap.as_rgba_bytes(), width as usize * 4)?;
+    Ok(png(width, height, PNG_RGBA, 8, &idat))
+}
+
+/// The pdfium library, loaded from next to the executable or from the system on first use.
+fn pdfium() -> anyhow::Result<&'static Pdfium> {
+    static PDFIUM: OnceLock<Result<Pdfium, String>> = OnceLock::new();
+
+    PDFIUM
+        .get_or_init(|| {
+            env::current_exe()
+                .ok()
+                .and_then(|exe| {
+                    let dir = exe.parent()?;
+                    Pdfium::bind_to_library(Pdfium::pdfium_platform_library_name_at_path(dir)).ok()
+                })
+                .map_or_else(Pdfium::bind_to_system_library, Ok)
+                .map(Pdfium::new)
+                .map_err(|e| e.to_string())
+        })
+        .as_ref()
+        .map_err(|e| anyhow::anyhow!("error loading pdfium: {}", e))
+}
+
+/// Drops the data of image streams while loading a document, as only their length
+/// and first bytes are needed to index them.
+fn strip_image_data(id: ObjectId, object: &mut Object) -> Option<(ObjectId, Object)> {
+    if let Object::Stream(stream) = object {
+        if name_of_direct(&stream.dict, b"Subtype") == Some(b"Image") {
+            stream.content.truncate(JP2_SIGNATURE.len());
+        }
+        // the returned object only replaces objects inside object streams, which are never streams
+        return Some((id, Object::Null));
+    }
+    Some((id, object.clone()))
+}
+
+/// Finds the image a page is made of, along with its width and height,
+/// or `None` if the page draws anything else or isn't drawn upright.
+fn page_image(
+    file: &File,
+    doc: &Document,
+    page_id: ObjectId,
+) -> anyhow::Result<Option<(PdfImage, u32, u32)>> {
+    let rotate = inherited(doc, page_id, b"Rotate")
+        .and_then(|v| v.as_i64().ok())
+        .unwrap_or(0);
+    if rotate.rem_euclid(360) != 0 {
+        return Ok(None);
+    }
+
+    let content = doc.get_and_decode_page_content(page_id)?;
+    let mut name = None;
+    for op in &content.operations {
+        match op.operator.as_str() {
+            // saving and restoring the graphics state draws nothing
+            "q" | "Q" => {}
+            // nor does scaling and moving, but rotating, flipping or skewing changes the orientation
+            "cm" if is_upright_scale(&op.operands) => {}
+            "Do" if name.is_none() => {
+                name = Some(op.operands.first().context("missing operand")?.as_name()?)
+            }
+            _ => return Ok(None),
+        }
+    }
+    let Some(name) = name else {
+        return Ok(None);
+    };
+
+    let (resources, resource_ids) = doc.get_page_resources(page_id)?;
+    let id = resources
+        .into_iter()
+        .chain(
+            resource_ids
+                .iter()
+                .filter_map(|id| doc.get_dictionary(*id).ok()),
+        )
+        .find_map(|resources| {
+            resources
+                .get_deref(b"XObject", doc)
+                .and_then(Object::as_dict)
+                .and_then(|v| v.get(name))
+                .and_then(Object::as_reference)
+                .ok()
+        })
+        .context("missing XObject")?;
+    let stream = doc.get_object(id)?.as_stream()?;
+    let dict = &stream.dict;
+    if name_of(doc, dict, b"Subtype") != Some(b"Image") {
+        return Ok(None);
+    }
+
+    // masks and decode arrays change the pixels, let pdfium apply them
+    if [&b"ImageMask"[..], b"Mask", b"SMask", b"Decode"]
+        .iter()
+        .any(|key| dict.has(key))
+    {
+        return Ok(None);
+    }
+
+    let width = int_of(doc, dict, b"Width").context("missing width")?;
+    let height = int_of(doc, dict, b"Height").context("missing height")?;
+    anyhow::ensure!(width > 0 && height > 0, "empty image");
+
+    let filter = match dict.get_deref(b"Filter", doc) {
+        Ok(Object::Name(v)) => v.as_slice(),
+        Ok(Object::Array(v)) if v.len() == 1 => v[0].as_name()?,
+        _ => return Ok(None),
+    };
+    let components = color_components(doc, dict);
+    let encoding = match filter {
+        b"DCTDecode" if matches!(components, Some(1 | 3)) => ImageEncoding::Jpeg,
+        b"JPXDecode" if stream.content.starts_with(JP2_SIGNATURE) => ImageEncoding::Jp2,
+        b"JPXDecode" => ImageEncoding::J2k,
+        b"FlateDecode" => {
+            let bit_depth = int_of(doc, dict, b"BitsPerComponent").unwrap_or(8);
+            let color_type = match (components, bit_depth) {
+                (Some(1), 1 | 2 | 4 | 8 | 16) => PNG_GRAYSCALE,
+                (Some(3), 8 | 16) => PNG_RGB,
+                _ => return Ok(None),
+            };
+
+            let params = match dict.get_deref(b"DecodeParms", doc) {
+                Ok(Object::Dictionary(v)) => Some(v),
+                Ok(Object::Array(v)) if v.len() == 1 => Some(doc.dereference(&v[0])?.1.as_dict()?),
+                _ => None,
+            };
+            let param =
+                |key: &[u8], default| params.and_then(|v| int_of(doc, v, key)).unwrap_or(default);
+            let predicted = match param(b"Predictor", 1) {
+                1 => false,
+                // PNG predictors, which must describe the same rows as the image
+                10..=15
+                    if param(b"Colors", 1) == components.unwrap_or(0)
+                        && param(b"BitsPerComponent", 8) == bit_depth
+                        && param(b"Columns", 1) == width =>
+                {
+                    true
+                }
+                _ => return Ok(None),
+            };
+
+            ImageEncoding::Flate {
+                color_type,
+                bit_depth: bit_depth as u8,
+                predicted,
+            }
+        }
+        _ => return Ok(None),
+    };
+
+    let Some((data_offset, len)) = stored_data(file, doc, id, stream) else {
+        return Ok(None);
+    };
+
+    Ok(Some((
+        PdfImage {
+            data_offset,
+            len,
+            encoding,
+        },
+        width,
+        height,
+    )))
+}
+
+/// Finds where the data of a stream is in the file and its length,
+/// checking it is stored there as is, e.g. not encrypted.
+///
+/// Only the start of image streams is kept in memory, see [`strip_image_data`].
+fn stored_data(file: &File, doc: &Document, id: ObjectId, stream: &Stream) -> Option<(u64, u64)> {
+    const KEYWORD: &[u8] = b"stream";
+    const END: &[u8] = b"endstream";
+    // how far from the start of the object its data can be, image dictionaries are short
+    const MAX_HEADER: usize = 4096;
+
+    if doc.trailer.has(b"Encrypt") {
+        return None;
+    }
+    let Some(XrefEntry::Normal { offset, generation }) = doc.reference_table.get(id.0) else {
+        return None;
+    };
+    if *generation != id.1 {
+        return None;
+    }
+    let len: u64 = stream
+        .dict
+        .get_deref(b"Length", doc)
+        .and_then(Object::as_i64)
+        .ok()?
+        .try_into()
+        .ok()?;
+
+    let mut header = vec![0; MAX_HEADER];
+    let read = file.read_at(*offset as u64, &mut header).ok()?;
+    let header = &header[..read];
+    let keyword = header.windows(KEYWORD.len()).position(|v| v == KEYWORD)?;
+    let mut start = keyword + KEYWORD.len();
+    if header[start..].starts_with(b"\r\n") {
+        start += 2;
+    } else if header[start..].starts_with(b"\n") {
+        start += 1;
+    } else {
+        return None;
+    }
+    let start = *offset as u64 + start as u64;
+
+    let mut head = vec![0; stream.content.len().min(len as usize)];
+    file.read_exact_at(start, &mut head).ok()?;
+    if !stream.content.starts_with(&head) {
+        return None;
+    }
+
+    // the data must be followed by the end of the stream, or the length is wrong
+    let mut tail = [0; 2 + END.len()];
+    let read = file.read_at(start.checked_add(len)?, &mut tail).ok()?;
+    let tail = tail[..read].trim_ascii_start();
+    tail.starts_with(END).then_some((start, len))
+}
+
+/// Checks that the operands of a `cm` operator only scale and translate,
+/// keeping what is drawn upright.
+fn is_upright_scale(operands: &[Object]) -> bool {
+    let matrix = operands
+        .iter()
+        .map(Object::as_float)
+        .collect::<Result<Vec<_>, _>>();
+    matches!(
+        matrix.as_deref(),
+        Ok(&[a, b, c, d, _, _]) if a > 0.0 && b == 0.0 && c == 0.0 && d > 0.0
+    )
+}
+
+/// Size of a page rasterized at [`RENDER_DPI`], from its crop box and rotation.
+fn render_size(doc: &Document, page_id: ObjectId) -> anyhow::Result<(u32, u32)> {
+    let page_box = inherited(doc, page_id, b"CropBox")
+        .or_else(|| inherited(doc, page_id, b"MediaBox"))
+        .context("missing media box")?
+        .as_array()?
+        .iter()
+        .map(|v| doc.dereference(v).and_then(|(_, v)| v.as_float()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let [x0, y0, x1, y1] = page_box[..] else {
+        anyhow::bail!("invalid media box");
+    };
+    let (mut width, mut height) = ((x1 - x0).abs(), (y1 - y0).abs());
+    anyhow::ensure!(width > 0.0 && height > 0.0, "empty media box");
+
+    let rotate = inherited(doc, page_id, b"Rotate")
+        .and_then(|v| v.as_i64().ok())
+        .unwrap_or(0);
+    if rotate.rem_euclid(180) == 90 {
+        (width, height) = (height, width);
+    }
+
+    let scale = (RENDER_DPI / 72.0).min(MAX_RENDER_SIZE / width.max(height));
+    Ok((
+        ((width * scale).round() as u32).max(1),
+        ((height * scale).round() as u32).max(1),
+    ))
+}
+
+/// Looks up an attribute of a page, which can be set on any of its parents in the page tree.
+fn inherited<'a>(doc: &'a Document, page_id: ObjectId, key: &[u8]) -> Option<&'a Object> {
+    let mut node = doc.get_dictionary(page_id).ok()?;
+    for _ in 0..MAX_TREE_DEPTH {
+        if let Ok(v) = node.get_deref(key, doc) {
+            return Some(v);
+        }
+        node = node
+            .get(b"Parent")
+            .and_then(Object::as_reference)
+            .and_then(|id| doc.get_dictionary(id))
+            .ok()?;
+    }
+    None
+}
+
+/// Number of color components of an image, if it is gray or RGB.
+fn color_components(doc: &Document, dict: &Dictionary) -> Option<u32> {
+    match dict.get_deref(b"ColorSpace", doc).ok()? {
+        Object::Name(v) => match v.as_slice() {
+            b"DeviceGray" | b"CalGray" => Some(1),
+            b"DeviceRGB" | b"CalRGB" => Some(3),
+            _ => None,
+        },
+        Object::Array(v) => match v.first()?.as_name().ok()? {
+            b"CalGray" => Some(1),
+            b"CalRGB" => Some(3),
+            b"ICCBased" => {
+                let profile = doc.dereference(v.get(1)?).ok()?.1.as_stream().ok()?;
+                int_of(doc, &profile.dict, b"N").filter(|n| matches!(n, 1 | 3))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn name_of<'a>(doc: &'a Document, dict: &'a Dictionary, key: &[u8]) -> Option<&'a [u8]> {
+    dict.get_deref(key, doc).and_then(Object::as_name).ok()
+}
+
+/// Like [`name_of`], for when the document isn't loaded yet.
+fn name_of_direct<'a>(dict: &'a Dictionary, key: &[u8]) -> Option<&'a [u8]> {
+    dict.get(key).and_then(Object::as_name).ok()
+}
+
+fn int_of(doc: &Document, dict: &Dictionary, key: &[u8]) -> Option<u32> {
+    let v = dict.get_deref(key, doc).and_then(Object::as_i64).ok()?;
+    v.try_into().ok()
+}
+
+/// Number of bytes in a row of pixels, rows starting on a byte boundary.
+fn row_len(width: u32, color_type: u8, bit_depth: u8) -> u64 {
+    let components = match color_type {
+        PNG_GRAYSCALE => 1,
+        PNG_RGB => 3,
+        _ => 4,
+    };
+    (width as u64 * components * bit_depth as u64).div_ceil(8)
+}
+
+/// Compresses rows of pixels as PNG image data, without filtering them.
+fn filter_rows(pixels: &[u8], row_len: usize) -> io::Result<Vec<u8>> {
+    let mut idat = ZlibEncoder::new(Vec::new(), Compression::fast());
+    for row in pixels.chunks(row_len) {
+        idat.write_all(&[0])?;
+        idat.write_all(row)?;
+    }
+    idat.finish()
+}
+
+/// Writes a PNG file around already compressed image data.
+fn png(width: u32, height: u32, color_type: u8, bit_depth: u8, idat: &[u8]) -> Vec<u8> {
+    let mut png = Vec::with_capacity(idat.len() + PNG_OVERHEAD as usize);
+    png.extend_from_slice(b"\x89PNG\r\n\x1a\n");
+
+    let mut ihdr = [0; 13];
+    ihdr[0..4].copy_from_slice(&width.to_be_bytes());
+    ihdr[4..8].copy_from_slice(&height.to_be_bytes());
+    ihdr[8] = bit_depth;
+    ihdr[9] = color_type;
+    png_chunk(&mut png, b"IHDR", &ihdr);
+    png_chunk(&mut png, b"IDAT", idat);
+    png_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    let mut crc = Crc::new();
+    crc.update(kind);
+    crc.update(data);
+
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    png.extend_from_slice(&crc.sum().to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn upright_scales() {
+        let matrix = |v: [f32; 6]| v.map(Object::Real);
+        assert!(is_upright_scale(&matrix([2.0, 0.0, 0.0, 3.0, 10.0, -5.0])));
+        assert!(is_upright_scale(&[1, 0, 0, 1, 0, 0].map(Object::Integer)));
+        assert!(!is_upright_scale(&matrix([0.0, 1.0, -1.0, 0.0, 0.0, 0.0])));
+        assert!(!is_upright_scale(&matrix([-1.0, 0.0, 0.0, 1.0, 0.0, 0.0])));
+        assert!(!is_upright_scale(&matrix([1.0, 0.5, 0.0, 1.0, 0.0, 0.0])));
+        assert!(!is_upright_scale(
+            &matrix([1.0, 0.0, 0.0, 1.0, 0.0, 0.0])[..4]
+        ));
+    }
+
+    #[test]
+    fn row_lengths() {
+        assert_eq!(row_len(10, PNG_GRAYSCALE, 1), 2);
+        assert_eq!(row_len(10, PNG_GRAYSCALE, 8), 10);
+        assert_eq!(row_len(10, PNG_RGB, 8), 30);
+        assert_eq!(row_len(10, PNG_RGB, 16), 60);
+        assert_eq!(row_len(10, PNG_RGBA, 8), 40);
+    }
+
+    #[test]
+    fn png_file() {
+        let (width, height) = (3, 2);
+        let pixels = (0..18).collect::<Vec<u8>>();
+        let idat = filter_rows(&pixels, row_len(width, PNG_RGB, 8) as usize).unwrap();
+        let png = png(width, height, PNG_RGB, 8, &idat);
+
+        assert_eq!(png.len() as u64, idat.len() as u64 + PNG_OVERHEAD);
+        assert_eq!(png[..8], *b"\x89PNG\r\n\x1a\n");
+        assert_eq!(png[8..16], *b"\0\0\0\x0dIHDR");
+        assert_eq!(png[16..29], [0, 0, 0, 3, 0, 0, 0, 2, 8, PNG_RGB, 0, 0, 0]);
+        // the well-known CRC of an empty IEND chunk
+        assert_eq!(png[png.len() - 12..], *b"\0\0\0\0IEND\xae\x42\x60\x82");
+
+        let size = imagesize::blob_size(&png).unwrap();
+        assert_eq!((size.width, size.height), (3, 2));
+
+        let mut rows = Vec::new();
+        ZlibDecoder::new(&png[41..png.len() - 16])
+            .read_to_end(&mut rows)
+            .unwrap();
+        assert_eq!(rows[..10], [0, 0, 1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(rows[10], 0);
+        assert_eq!(rows[11..], pixels[9..]);
+    }
+}
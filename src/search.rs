@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+use crate::load::MangaEntry;
+
+/// Bonus for a title containing the whole query, so phrase matches rank first.
+const PHRASE_BONUS: u32 = 20;
+/// Bonus for a title equal to the query.
+const EXACT_TITLE_BONUS: u32 = 50;
+
+/// The manga fields that are searched, by decreasing relevance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Field {
+    Description = 1,
+    Tags = 4,
+    Artists = 5,
+    Authors = 6,
+    Title = 10,
+}
+
+/// The documents containing a word, along with the best field it appears in.
+type Postings = Vec<(u32, Field)>;
+
+/// An in-memory full text index over the manga of a library.
+///
+/// Documents are identified by their position in the library order.
+#[derive(Debug, Clone, Default)]
+pub struct SearchIndex {
+    /// Normalized words, sorted so prefixes can be looked up with a binary search.
+    words: Vec<(Box<str>, Postings)>,
+    /// Normalized title words joined by spaces, for phrase matches.
+    titles: Vec<String>,
+}
+
+impl SearchIndex {
+    pub fn new<'a>(mangas: impl IntoIterator<Item = &'a MangaEntry>) -> Self {
+        let mut words = HashMap::<String, Postings>::new();
+        let mut titles = Vec::new();
+
+        for (doc, manga) in mangas.into_iter().enumerate() {
+            let doc = doc.try_into().expect("over u32::MAX manga");
+            let description = normalize(&manga.description);
+            let fields = [
+                (Field::Title, &manga.normalized.title),
+                (Field::Authors, &manga.normalized.authors),
+                (Field::Artists, &manga.normalized.artists),
+                (Field::Description, &description),
+            ];
+            let tags = manga.normalized.tags.iter().map(|tag| (Field::Tags, tag));
+
+            for (field, text) in fields.into_iter().chain(tags) {
+                for word in words_of(text) {
+                    let postings = words.entry(word.to_owned()).or_default();
+                    match postings.last_mut() {
+                        Some((last, best)) if *last == doc => *best = field.max(*best),
+                        _ => postings.push((doc, field)),
+                    }
+                }
+            }
+
+            titles.push(
+                words_of(&manga.normalized.title)
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            );
+        }
+
+        let mut words = words
+            .into_iter()
+            .map(|(word, postings)| (word.into_boxed_str(), postings))
+            .collect::<Vec<_>>();
+        words.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+        Self { words, titles }
+    }
+
+    /// Returns the documents matching every word of `query`, best matches first.
+    ///
+    /// The last word of the query also matches words it is a prefix of,
+    /// so results can be shown while typing.
+    pub fn search(&self, query: &str) -> Vec<u32> {
+        let query = normalize(query);
+        let terms = words_of(&query).collect::<Vec<_>>();
+        let Some((last, terms)) = terms.split_last() else {
+            return Vec::new();
+        };
+
+        let mut scores: Option<HashMap<u32, u32>> = None;
+        let matches = terms
+            .iter()
+            .map(|term| self.matches(term, false))
+            .chain([self.matches(last, true)]);
+        for term_scores in matches {
+            scores = Some(match scores {
+                None => term_scores,
+                Some(mut scores) => {
+                    scores.retain(|doc, score| match term_scores.get(doc) {
+                        Some(v) => {
+                            *score += v;
+                            true
+                        }
+                        None => false,
+                    });
+                    scores
+                }
+            });
+        }
+
+        let phrase = terms
+            .iter()
+            .chain([last])
+            .copied()
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut results = scores
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(doc, mut score)| {
+                let title = &self.titles[doc as usize];
+                if *title == phrase {
+                    score += EXACT_TITLE_BONUS;
+                } else if title.contains(&phrase) {
+                    score += PHRASE_BONUS;
+                }
+                (doc, score)
+            })
+            .collect::<Vec<_>>();
+
+        results.sort_unstable_by(|(a_doc, a), (b_doc, b)| b.cmp(a).then(a_doc.cmp(b_doc)));
+        results.into_iter().map(|(doc, _)| doc).collect()
+    }
+
+    /// Scores the documents containing `term`, counting whole words twice as much as prefixes.
+    fn matches(&self, term: &str, prefix: bool) -> HashMap<u32, u32> {
+        let start = self.words.partition_point(|(word, _)| &**word < term);
+        let words = self.words[start..]
+            .iter()
+            .take_while(|(word, _)| match prefix {
+                true => word.starts_with(term),
+                false => &**word == term,
+            });
+
+        let mut scores = HashMap::new();
+        for (word, postings) in words {
+            let multiplier = if &**word == term { 2 } else { 1 };
+            for &(doc, field) in postings {
+                let score = scores.entry(doc).or_insert(0);
+                *score = (field as u32 * multiplier).max(*score);
+            }
+        }
+        scores
+    }
+}
+
+/// Lowercases `text` and strips accents, so "Café" matches "cafe".
+pub fn normalize(text: &str) -> String {
+    text.nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Splits a comma separated list, normalizing each item.
+pub fn normalize_list(text: &str) -> Vec<String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(normalize)
+        .collect()
+}
+
+fn words_of(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{
+        load::{MangaStatus, NormalizedText},
+        server::JsonBytes,
+    };
+
+    fn manga(title: &str, authors: &str, tags: &str, description: &str) -> MangaEntry {
+        MangaEntry {
+            path: PathBuf::new(),
+            title: title.into(),
+            authors: authors.into(),
+            tags: tags.into(),
+            description: description.into(),
+            status: MangaStatus::default(),
+            normalized: NormalizedText {
+                title: normalize(title),
+                authors: normalize(authors),
+                artists: String::new(),
+                tags: normalize_list(tags),
+            },
+            added: 0,
+            latest_chapter: 0,
+            json: JsonBytes::new(Box::default()),
+            cover: None,
+            chapters: Box::default(),
+        }
+    }
+
+    fn index() -> SearchIndex {
+        let mangas = [
+            manga("One Piece", "Eiichiro Oda", "Action, Adventure", "Pirates."),
+            manga("Piece of Cake", "", "Comedy", ""),
+            manga(
+                "Café Stories",
+                "Zoë Müller",
+                "Slice of Life",
+                "Coffee and pieces of cake.",
+            ),
+            manga("One", "", "", ""),
+        ];
+        SearchIndex::new(&mangas)
+    }
+
+    #[test]
+    fn normalization() {
+        assert_eq!(normalize("Café ZOË"), "cafe zoe");
+        assert_eq!(normalize("ﬁne"), "fine");
+        assert_eq!(
+            normalize_list(" Action, ,Slice of Life "),
+            ["action", "slice of life"]
+        );
+        assert_eq!(
+            words_of("one-piece, vol.2").collect::<Vec<_>>(),
+            ["one", "piece", "vol", "2"]
+        );
+    }
+
+    #[test]
+    fn matches_every_word() {
+        let index = index();
+        assert_eq!(index.search("cafe"), [2]);
+        assert_eq!(index.search("PIECE oda"), [0]);
+        assert_eq!(index.search("comedy"), [1]);
+        assert_eq!(index.search("piece pirates slice"), Vec::<u32>::new());
+        assert_eq!(index.search(" , "), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn last_word_is_a_prefix() {
+        let index = index();
+        assert_eq!(index.search("advent"), [0]);
+        assert_eq!(index.search("advent oda"), Vec::<u32>::new());
+        assert_eq!(index.search("stor"), [2]);
+    }
+
+    #[test]
+    fn ranking() {
+        let index = index();
+        // an exact title first, then a phrase, then other title matches
+        assert_eq!(index.search("one"), [3, 0]);
+        assert_eq!(index.search("piece of"), [1]);
+        // titles rank above descriptions, where "pieces" only matches as a prefix
+        assert_eq!(index.search("piece"), [0, 1, 2]);
+        assert_eq!(index.search("cake"), [1, 2]);
+    }
+}
@@ -1,44 +1,187 @@
 use std::{
     convert::Infallible,
     fmt::{self, Debug, Display},
-    fs::{self, File},
+    fs::File,
     io::{self, Read, Seek, Write},
-    net::{Ipv6Addr, TcpListener},
-    ops::Deref,
+    num::NonZeroUsize,
+    sync::Arc,
+    time::SystemTime,
 };
 
 use anyhow::Context;
 use bstr::ByteSlice;
-use flate2::read::DeflateDecoder;
-use futures::TryFutureExt;
-use log::{error, info};
+use bytes::Bytes;
+use flate2::Compression;
+use futures::{FutureExt, TryFutureExt};
+use log::{debug, error, info};
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use subtle::ConstantTimeEq;
 
 use http::{
-    header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE},
+    header::{
+        ACCEPT_ENCODING, ACCEPT_RANGES, AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH,
+        CONTENT_RANGE, CONTENT_TYPE, VARY, WWW_AUTHENTICATE, X_CONTENT_TYPE_OPTIONS,
+    },
     HeaderMap, HeaderValue, Method, Request, StatusCode,
 };
 use hyper::{
+    server::accept::Accept,
     service::{make_service_fn, service_fn},
     Body,
 };
-use tokio::signal::ctrl_c;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    signal::ctrl_c,
+};
+
+#[cfg(any(feature = "rar", feature = "7z", feature = "tar", feature = "pdf"))]
+use self::extract::ExtractCache;
+#[cfg(feature = "7z")]
+use crate::load::extract_7z;
+#[cfg(feature = "rar")]
+use crate::load::extract_rar;
+#[cfg(feature = "tar")]
+use crate::load::{extract_tar_gz, TarEntry};
+use crate::load::{CoverEntry, FilePage, Library, MangaEntry, Pages};
+#[cfg(feature = "pdf")]
+use crate::pdf::{self, PdfPage};
+#[cfg(any(
+    feature = "zip",
+    feature = "rar",
+    feature = "7z",
+    feature = "tar",
+    feature = "pdf"
+))]
+use std::path::Path;
+#[cfg(feature = "pdf")]
+use tokio::sync::Semaphore;
+#[cfg(any(feature = "rar", feature = "7z", feature = "tar"))]
+use {
+    crate::load::ArchiveEntry,
+    std::{fs, slice},
+};
+#[cfg(feature = "zip")]
+use {crate::load::ZipEntry, flate2::read::DeflateDecoder};
+
+use self::{
+    body::{stream_body, Verified},
+    cache::{set_cache_control, Validators},
+    listen::Listener,
+    listing::ListingQuery,
+    query::{only_params, parse_query, Details, Paging},
+    range::{byte_range, ByteRange},
+};
+
+#[cfg(unix)]
+pub use self::listen::take_listen_fds;
+#[cfg(feature = "tls")]
+pub use self::tls::TlsConfig;
+pub use self::{
+    auth::{hash_password, Auth},
+    cache::CachePolicy,
+    listen::Listen,
+};
 
-use crate::load::{Cover, LibraryEntry, MangaEntry, Pages};
+mod auth;
+mod body;
+mod cache;
+#[cfg(any(feature = "rar", feature = "7z", feature = "tar", feature = "pdf"))]
+mod extract;
+mod listen;
+mod listing;
+mod query;
+mod range;
+#[cfg(feature = "tls")]
+mod tls;
 
 type Response<T = Body> = http::Response<T>;
 
+/// Default maximum size of the pages extracted from solid archives or converted kept in memory,
+/// including the ones being extracted.
+pub const DEFAULT_EXTRACT_CACHE_SIZE: u64 = 512 * 1024 * 1024;
+/// Maximum number of PDF pages converted at once.
+#[cfg(feature = "pdf")]
+const PDF_CONVERSIONS: usize = 2;
+
 #[derive(Debug, Default)]
 pub struct ServerBuilder {
-    port: u16,
+    listen: Vec<Listen>,
+    cache: CachePolicy,
+    threads: Option<NonZeroUsize>,
+    admin_token: Option<String>,
+    auth: Option<Auth>,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConfig>,
+    #[cfg(any(feature = "rar", feature = "7z", feature = "tar", feature = "pdf"))]
+    extract_cache_size: u64,
 }
 
 impl ServerBuilder {
-    pub fn new(port: u16) -> Self {
-        Self { port }
+    pub fn new(listen: Vec<Listen>) -> Self {
+        Self {
+            listen,
+            cache: CachePolicy::default(),
+            threads: None,
+            admin_token: None,
+            auth: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+            #[cfg(any(feature = "rar", feature = "7z", feature = "tar", feature = "pdf"))]
+            extract_cache_size: DEFAULT_EXTRACT_CACHE_SIZE,
+        }
+    }
+
+    pub fn cache_policy(mut self, cache: CachePolicy) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Uses a multi-threaded runtime with the given number of worker threads,
+    /// instead of running everything on the current thread.
+    pub fn threads(mut self, threads: Option<NonZeroUsize>) -> Self {
+        self.threads = threads;
+        self
     }
 
-    pub fn run(self, lib: LibraryEntry) -> anyhow::Result<()> {
-        tokio::runtime::Builder::new_current_thread()
+    /// Enables the `/_admin` routes, authenticated with the given bearer token.
+    pub fn admin_token(mut self, token: Option<String>) -> Self {
+        self.admin_token = token;
+        self
+    }
+
+    /// Requires clients to authenticate with one of the given credentials.
+    pub fn auth(mut self, auth: Option<Auth>) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Serves HTTPS instead of plain HTTP.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: Option<TlsConfig>) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Sets the memory used to keep pages extracted from solid archives or converted,
+    /// 0 extracting them one at a time on every request.
+    #[cfg(any(feature = "rar", feature = "7z", feature = "tar", feature = "pdf"))]
+    pub fn extract_cache_size(mut self, size: u64) -> Self {
+        self.extract_cache_size = size;
+        self
+    }
+
+    pub fn run(self, lib: Arc<Library>) -> anyhow::Result<()> {
+        let mut runtime = match self.threads {
+            None => tokio::runtime::Builder::new_current_thread(),
+            Some(threads) => {
+                let mut runtime = tokio::runtime::Builder::new_multi_thread();
+                runtime.worker_threads(threads.get());
+                runtime
+            }
+        };
+
+        runtime
             .enable_all()
             .build()
             .context("Failed to build async runtime")?
@@ -46,23 +189,104 @@ impl ServerBuilder {
     }
 }
 
-async fn run_server(builder: ServerBuilder, lib: LibraryEntry) -> anyhow::Result<()> {
-    let ServerBuilder { port } = builder;
+async fn run_server(builder: ServerBuilder, lib: Arc<Library>) -> anyhow::Result<()> {
+    let ServerBuilder {
+        listen,
+        cache,
+        admin_token,
+        auth,
+        #[cfg(feature = "tls")]
+        tls,
+        #[cfg(any(feature = "rar", feature = "7z", feature = "tar", feature = "pdf"))]
+        extract_cache_size,
+        ..
+    } = builder;
+
+    let mut listeners = Vec::new();
+    for listen in &listen {
+        listeners.extend(
+            listen
+                .bind()
+                .with_context(|| format!("{}: error binding listener", listen))?,
+        );
+    }
+    anyhow::ensure!(!listeners.is_empty(), "no sockets to listen on");
 
-    let tcp = TcpListener::bind((Ipv6Addr::UNSPECIFIED, port))?;
+    #[cfg(feature = "tls")]
+    let acceptor = tls.map(tls::acceptor).transpose()?;
 
-    info!(
-        "hosting server at {}, serving {} manga",
-        tcp.local_addr()?,
-        lib.mangas.len()
-    );
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
 
-    let shared = &*Box::leak(Box::new(Shared { lib }));
+        let mut hangup = signal(SignalKind::hangup()).context("Failed to listen for SIGHUP")?;
+        let lib = lib.clone();
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                info!("received SIGHUP");
+                let lib = lib.clone();
+                tokio::task::spawn_blocking(move || lib.rescan());
+            }
+        });
+    }
 
-    let make_service =
-        make_service_fn(|_conn| async { Ok::<_, Infallible>(service_fn(|req| shared.serve(req))) });
+    info!("serving {} manga", lib.get().mangas.len());
+
+    let shared = &*Box::leak(Box::new(Shared {
+        lib,
+        cache,
+        admin_token,
+        auth,
+        #[cfg(any(feature = "rar", feature = "7z", feature = "tar", feature = "pdf"))]
+        extract: ExtractCache::new(extract_cache_size),
+        #[cfg(feature = "pdf")]
+        pdf_conversions: Semaphore::new(PDF_CONVERSIONS),
+    }));
+
+    let mut servers = Vec::new();
+    for listener in listeners {
+        let name = listener.to_string();
+        match listener {
+            Listener::Tcp(tcp) => {
+                #[cfg(feature = "tls")]
+                if let Some(acceptor) = &acceptor {
+                    info!("hosting server at https://{}", name);
+                    tcp.set_nonblocking(true)?;
+                    let tcp = tokio::net::TcpListener::from_std(tcp)?;
+                    let incoming = tls::incoming(tcp, acceptor.clone());
+                    servers.push(serve(hyper::Server::builder(incoming), shared).boxed());
+                    continue;
+                }
 
-    hyper::Server::from_tcp(tcp)?
+                info!("hosting server at http://{}", name);
+                servers.push(serve(hyper::Server::from_tcp(tcp)?, shared).boxed());
+            }
+            #[cfg(unix)]
+            Listener::Unix(unix) => {
+                info!("hosting server at {}", name);
+                unix.set_nonblocking(true)?;
+                let incoming = listen::unix_incoming(tokio::net::UnixListener::from_std(unix)?);
+                servers.push(serve(hyper::Server::builder(incoming), shared).boxed());
+            }
+        }
+    }
+
+    futures::future::try_join_all(servers).await?;
+    Ok(())
+}
+
+/// Serves requests from the connections accepted by `server` until the process is interrupted.
+async fn serve<I>(server: hyper::server::Builder<I>, shared: &'static Shared) -> anyhow::Result<()>
+where
+    I: Accept,
+    I::Conn: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let make_service = make_service_fn(|_conn: &I::Conn| async {
+        Ok::<_, Infallible>(service_fn(|req| shared.serve(req)))
+    });
+
+    server
         .serve(make_service)
         .with_graceful_shutdown(ctrl_c().unwrap_or_else(|_| ()))
         .await?;
@@ -71,7 +295,15 @@ async fn run_server(builder: ServerBuilder, lib: LibraryEntry) -> anyhow::Result
 }
 
 struct Shared {
-    lib: LibraryEntry,
+    lib: Arc<Library>,
+    cache: CachePolicy,
+    admin_token: Option<String>,
+    auth: Option<Auth>,
+    #[cfg(any(feature = "rar", feature = "7z", feature = "tar", feature = "pdf"))]
+    extract: ExtractCache,
+    /// Bounds the PDF pages converted at once, rasterizing taking a lot of memory.
+    #[cfg(feature = "pdf")]
+    pdf_conversions: Semaphore,
 }
 
 impl Shared {
@@ -80,15 +312,44 @@ impl Shared {
     }
 
     async fn route(&'static self, req: &Request<Body>) -> Result<Response<Body>, Error> {
+        if let Some(path) = req.uri().path().strip_prefix("/_admin/") {
+            return self.route_admin(req, path);
+        }
+
+        if let Some(auth) = &self.auth {
+            if !auth.is_authorized(req.headers()).await? {
+                return Ok(unauthorized(auth.challenges()));
+            }
+        }
+
         if Method::GET != *req.method() {
             return Err(StatusCode::METHOD_NOT_ALLOWED.into());
         }
 
         let mut path = req.uri().path().split('/').skip(1);
 
-        let manga = match path.next() {
+        let lib = self.lib.get();
+        let (id, manga) = match path.next() {
             None | Some("") => return self.serve_lib(req).await,
-            Some(manga) => self.lib.mangas.get(manga).ok_or(Error::NOT_FOUND)?,
+            Some("search") => {
+                if path.next().is_some() {
+                    return Err(Error::NOT_FOUND);
+                }
+                return self.serve_search(req).await;
+            }
+            Some("latest") => {
+                if path.next().is_some() {
+                    return Err(Error::NOT_FOUND);
+                }
+                return self.serve_latest(req).await;
+            }
+            Some(manga) => {
+                let id = percent_decode_str(manga)
+                    .decode_utf8()
+                    .map_err(|_| Error::NOT_FOUND)?;
+                let manga = lib.mangas.get(&*id).ok_or(Error::NOT_FOUND)?.clone();
+                (id, manga)
+            }
         };
 
         let ch = match path.next() {
@@ -103,7 +364,7 @@ impl Shared {
         };
 
         let pg = match path.next() {
-            None => return Err(Error::NOT_FOUND),
+            None => return self.serve_chapter(req, &id, manga, ch).await,
             Some(pg) => pg.parse().map_err(|_| Error::NOT_FOUND)?,
         };
 
@@ -113,97 +374,641 @@ impl Shared {
         }
     }
 
+    fn route_admin(&'static self, req: &Request<Body>, path: &str) -> Result<Response, Error> {
+        let token = self.admin_token.as_ref().ok_or(Error::NOT_FOUND)?;
+
+        let authorized = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .is_some_and(|v| bool::from(v.trim().as_bytes().ct_eq(token.as_bytes())));
+        if !authorized {
+            return Ok(unauthorized(["Bearer"]));
+        }
+
+        match path {
+            "rescan" => {
+                if Method::POST != *req.method() {
+                    return Err(StatusCode::METHOD_NOT_ALLOWED.into());
+                }
+
+                let lib = self.lib.clone();
+                tokio::task::spawn_blocking(move || lib.rescan());
+
+                let mut resp = Response::new(Body::empty());
+                *resp.status_mut() = StatusCode::ACCEPTED;
+                Ok(resp)
+            }
+            _ => Err(Error::NOT_FOUND),
+        }
+    }
+
     async fn serve_lib(&'static self, req: &Request<Body>) -> Result<Response, Error> {
-        self.lib.json.to_response(req.headers())
+        let lib = self.lib.get();
+
+        // without filters or pagination, the whole library is sent as a plain array
+        let mut resp = match only_params(req, &["details"]) {
+            true => match parse_query::<Details>(req)?.details {
+                false => lib.json.to_response(req.headers())?,
+                true => lib.json_details.to_response(req.headers())?,
+            },
+            false => self.listing_response(req, parse_query(req)?)?,
+        };
+        set_cache_control(&mut resp, &self.cache.library);
+        Ok(resp)
+    }
+
+    async fn serve_latest(&'static self, req: &Request<Body>) -> Result<Response, Error> {
+        let query: ListingQuery = parse_query(req)?;
+        let mut resp = self.listing_response(req, query.latest())?;
+        set_cache_control(&mut resp, &self.cache.library);
+        Ok(resp)
+    }
+
+    fn listing_response(
+        &'static self,
+        req: &Request<Body>,
+        query: ListingQuery,
+    ) -> Result<Response, Error> {
+        let paging = Paging::from_request(req)?;
+        let Details { details } = parse_query(req)?;
+
+        let lib = self.lib.get();
+        let docs = query.apply(&lib).map_err(|_| Error::BAD_REQUEST)?;
+        let (docs, has_next_page) = paging.apply(&docs);
+        lib.listing_page(docs, has_next_page, details)?
+            .to_response(req.headers())
+    }
+
+    async fn serve_search(&'static self, req: &Request<Body>) -> Result<Response, Error> {
+        #[derive(Deserialize)]
+        struct SearchQuery {
+            q: String,
+        }
+
+        let query: SearchQuery = parse_query(req)?;
+        let paging = Paging::from_request(req)?;
+        let Details { details } = parse_query(req)?;
+
+        let lib = self.lib.get();
+        let results = lib.search.search(&query.q);
+        let (docs, has_next_page) = paging.apply(&results);
+
+        let mut resp = lib
+            .listing_page(docs, has_next_page, details)?
+            .to_response(req.headers())?;
+        set_cache_control(&mut resp, &self.cache.library);
+        Ok(resp)
     }
 
     async fn serve_manga(
         &'static self,
         req: &Request<Body>,
-        manga: &'static MangaEntry,
+        manga: Arc<MangaEntry>,
     ) -> Result<Response, Error> {
-        manga.json.to_response(req.headers())
+        let mut resp = manga.json.to_response(req.headers())?;
+        set_cache_control(&mut resp, &self.cache.manga);
+        Ok(resp)
     }
 
-    async fn serve_cover(
+    async fn serve_chapter(
         &'static self,
         req: &Request<Body>,
-        manga: &'static MangaEntry,
+        id: &str,
+        manga: Arc<MangaEntry>,
+        ch: usize,
     ) -> Result<Response, Error> {
-        let cover = manga.cover.as_ref().ok_or(Error::NOT_FOUND)?;
-
-        match cover {
-            Cover::File(path) => fs::read(path)
-                .map(|v| Response::new(v.into()))
-                .with_context(|| format!("{:?}: error opening cover", cover))
-                .map_err(Into::into),
-            &Cover::Page { ch, pg } => self
-                .serve_page(req, manga, ch, pg)
-                .await
-                .map_err(|e| e.with_context(|| format!("{:?}: error opening cover", cover))),
+        #[derive(Deserialize, Default)]
+        #[serde(default)]
+        struct ChapterQuery {
+            #[serde(deserialize_with = "query::flag")]
+            dimensions: bool,
         }
+
+        let query: ChapterQuery = parse_query(req)?;
+        if manga.chapters.get(ch).is_none() {
+            return Err(Error::NOT_FOUND);
+        }
+
+        let id = id.to_owned();
+        let json = blocking(move || {
+            let chapter = &manga.chapters[ch];
+            let dimensions = query.dimensions.then(|| {
+                (0..chapter.pages.len() as usize)
+                    .map(|pg| self.page_dimensions(&chapter.pages, pg))
+                    .collect::<Vec<_>>()
+            });
+            Ok(chapter.detail_json(&id, ch, dimensions.as_deref())?)
+        })
+        .await?;
+
+        let mut resp = json.to_response(req.headers())?;
+        set_cache_control(&mut resp, &self.cache.manga);
+        Ok(resp)
+    }
+
+    async fn serve_cover(
+        &'static self,
+        req: &Request<Body>,
+        manga: Arc<MangaEntry>,
+    ) -> Result<Response, Error> {
+        let mut resp = match manga.cover.as_ref().ok_or(Error::NOT_FOUND)? {
+            CoverEntry::File(page) => {
+                let page = page.clone();
+                let headers = req.headers().clone();
+                blocking(move || {
+                    Ok(serve_file(&headers, &page)
+                        .with_context(|| format!("{:?}: error opening cover", page.path))?)
+                })
+                .await?
+            }
+            &CoverEntry::Page { ch, pg } => {
+                self.serve_page(req, manga, ch, pg).await.map_err(|e| {
+                    e.with_context(|| {
+                        format!("{:?}: error opening cover", CoverEntry::Page { ch, pg })
+                    })
+                })?
+            }
+        };
+        set_cache_control(&mut resp, &self.cache.cover);
+        Ok(resp)
     }
 
     async fn serve_page(
         &'static self,
         req: &Request<Body>,
-        manga: &'static MangaEntry,
+        manga: Arc<MangaEntry>,
         ch: usize,
         pg: usize,
     ) -> Result<Response, Error> {
-        let ch = manga.chapters.get(ch).ok_or(Error::NOT_FOUND)?;
+        let mut resp = self.page_response(req, manga, ch, pg).await?;
+        set_cache_control(&mut resp, &self.cache.page);
+        Ok(resp)
+    }
 
-        match &ch.pages {
+    async fn page_response(
+        &'static self,
+        req: &Request<Body>,
+        manga: Arc<MangaEntry>,
+        ch: usize,
+        pg: usize,
+    ) -> Result<Response, Error> {
+        let chapter = manga.chapters.get(ch).ok_or(Error::NOT_FOUND)?;
+
+        match &chapter.pages {
             Pages::None => Err(Error::NOT_FOUND),
             Pages::Filesystem(pages) => {
-                let page = pages.get(pg).ok_or(Error::NOT_FOUND)?;
-                let ctx = || format!("{:?}: error opening page", page);
+                let page = pages.get(pg).ok_or(Error::NOT_FOUND)?.clone();
 
-                Ok(Response::new(fs::read(page).with_context(ctx)?.into()))
+                let headers = req.headers().clone();
+                blocking(move || {
+                    Ok(serve_file(&headers, &page)
+                        .with_context(|| format!("{:?}: error opening page", page.path))?)
+                })
+                .await
             }
             #[cfg(feature = "zip")]
             Pages::Zip(path, pages) => {
-                let page = pages.get(pg).ok_or(Error::NOT_FOUND)?;
-                let ctx = || format!("{:?}: error opening page", path);
+                let page = *pages.get(pg).ok_or(Error::NOT_FOUND)?;
+                let path = path.clone();
 
-                let mut file = File::open(path).with_context(ctx)?;
-                file.seek(io::SeekFrom::Start(page.data_offset))
-                    .with_context(ctx)?;
-                let mut file = file.take(page.compressed_size);
+                let headers = req.headers().clone();
+                blocking(move || serve_zip_entry(&headers, &path, &page)).await
+            }
+            #[cfg(feature = "tar")]
+            Pages::Tar {
+                path,
+                gzip: false,
+                pages,
+            } => {
+                let page = *pages.get(pg).ok_or(Error::NOT_FOUND)?;
+                let path = path.clone();
 
-                let mut buf =
-                    Vec::with_capacity(page.uncompressed_size.try_into().expect("usize overflow"));
+                let headers = req.headers().clone();
+                blocking(move || serve_tar_entry(&headers, &path, &page)).await
+            }
+            #[cfg(feature = "pdf")]
+            Pages::Pdf(path, pages) => {
+                let page = *pages.get(pg).ok_or(Error::NOT_FOUND)?;
+                let path = path.clone();
 
-                match page.method {
-                    rc_zip::Method::Store => {
-                        file.read_to_end(&mut buf).with_context(ctx)?;
-                    }
-                    rc_zip::Method::Deflate => {
-                        if req
-                            .headers()
-                            .get(ACCEPT_ENCODING)
-                            .map(|v| v.to_str().map_err(|_| Error::NOT_ACCEPTABLE))
-                            .transpose()?
-                            .map_or(false, |v| v.contains("deflate"))
-                        {
-                            file.read_to_end(&mut buf).with_context(ctx)?;
-                            let mut resp = Response::new(buf.into());
-                            resp.headers_mut()
-                                .insert(CONTENT_ENCODING, HeaderValue::from_static("deflate"));
-                            return Ok(resp);
-                        }
-
-                        DeflateDecoder::new(file)
-                            .read_to_end(&mut buf)
-                            .with_context(ctx)?;
-                    }
-                    _ => Err(anyhow::anyhow!("unsupported compression type")).with_context(ctx)?,
-                }
+                let _permit = match page.stored_image() {
+                    Some(_) => None,
+                    None => Some(
+                        self.pdf_conversions
+                            .acquire()
+                            .await
+                            .context("server closed")?,
+                    ),
+                };
 
-                Ok(Response::new(buf.into()))
+                let headers = req.headers().clone();
+                blocking(move || serve_pdf_page(&headers, &self.extract, &path, &page)).await
+            }
+            // the page index moves with the manga instead of being copied
+            #[cfg(any(feature = "rar", feature = "7z", feature = "tar"))]
+            _ => {
+                let headers = req.headers().clone();
+                blocking(move || self.serve_extracted(&headers, &manga.chapters[ch].pages, pg))
+                    .await
             }
         }
     }
+
+    /// Serves a page of a chapter whose archive is extracted to be read.
+    #[cfg(any(feature = "rar", feature = "7z", feature = "tar"))]
+    fn serve_extracted(
+        &self,
+        headers: &HeaderMap,
+        pages: &Pages,
+        pg: usize,
+    ) -> Result<Response, Error> {
+        let cache = &self.extract;
+        match pages {
+            #[cfg(feature = "rar")]
+            Pages::Rar { path, solid, pages } => {
+                serve_extracted(headers, cache, path, *solid, pages, pg, extract_rar)
+            }
+            #[cfg(feature = "7z")]
+            Pages::SevenZ { path, solid, pages } => {
+                serve_extracted(headers, cache, path, *solid, pages, pg, extract_7z)
+            }
+            #[cfg(feature = "tar")]
+            Pages::Tar { path, pages, .. } => {
+                serve_extracted(headers, cache, path, true, pages, pg, extract_tar_gz)
+            }
+            _ => Err(Error::NOT_FOUND),
+        }
+    }
+
+    /// Reads the width and height of a page from its header,
+    /// or `None` if the page can't be read or isn't an image.
+    fn page_dimensions(&self, pages: &Pages, pg: usize) -> Option<(usize, usize)> {
+        /// Enough to get past the metadata at the start of most JPEG files.
+        const HEADER_LEN: u64 = 64 * 1024;
+
+        let header = match pages {
+            // known without converting the page
+            #[cfg(feature = "pdf")]
+            Pages::Pdf(_, pages) => {
+                return pages.get(pg).map(|v| (v.width as usize, v.height as usize))
+            }
+            // reuse the extracted chapter instead of decompressing it again for each page
+            #[cfg(feature = "rar")]
+            Pages::Rar {
+                path,
+                solid: true,
+                pages,
+            } => extract_page(&self.extract, path, pages, pg, extract_rar)
+                .map(|page| page.slice(..page.len().min(HEADER_LEN as usize)).to_vec()),
+            #[cfg(feature = "7z")]
+            Pages::SevenZ {
+                path,
+                solid: true,
+                pages,
+            } => extract_page(&self.extract, path, pages, pg, extract_7z)
+                .map(|page| page.slice(..page.len().min(HEADER_LEN as usize)).to_vec()),
+            #[cfg(feature = "tar")]
+            Pages::Tar {
+                path,
+                gzip: true,
+                pages,
+            } => extract_page(&self.extract, path, pages, pg, extract_tar_gz)
+                .map(|page| page.slice(..page.len().min(HEADER_LEN as usize)).to_vec()),
+            _ => pages.read_start(pg, HEADER_LEN),
+        };
+
+        let header = header
+            .map_err(|e| debug!("error reading page #{}: {:#}", pg, e))
+            .ok()?;
+        let size = imagesize::blob_size(&header).ok()?;
+        Some((size.width, size.height))
+    }
+}
+
+/// Runs blocking file IO on the blocking thread pool,
+/// so it doesn't stall other requests.
+async fn blocking<T, F>(f: F) -> Result<T, Error>
+where
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .context("blocking task failed")?
+}
+
+/// Serves an entry of a zip file.
+///
+/// Stored entries support range requests, deflated entries are sent
+/// as is if the client accepts it, or inflated otherwise.
+#[cfg(feature = "zip")]
+fn serve_zip_entry(headers: &HeaderMap, path: &Path, page: &ZipEntry) -> Result<Response, Error> {
+    let ctx = || format!("{:?}: error opening page", path);
+
+    let mut file = File::open(path).with_context(ctx)?;
+    let modified = file.metadata().and_then(|v| v.modified()).ok();
+    let validators = Validators::new(
+        (path, page.data_offset, page.compressed_size, modified),
+        modified,
+    );
+
+    if let rc_zip::Method::Store = page.method {
+        return Ok(serve_slice(
+            headers,
+            file,
+            page.data_offset,
+            page.compressed_size,
+            page.mime,
+            &validators,
+            Some(page.crc32),
+        )
+        .with_context(ctx)?);
+    }
+
+    let passthrough = match page.method {
+        rc_zip::Method::Deflate => headers
+            .get(ACCEPT_ENCODING)
+            .map(|v| v.to_str().map_err(|_| Error::NOT_ACCEPTABLE))
+            .transpose()?
+            .is_some_and(|v| v.contains("deflate")),
+        _ => Err(anyhow::anyhow!("unsupported compression type")).with_context(ctx)?,
+    };
+
+    let validators = match passthrough {
+        true => validators.with_encoding("deflate"),
+        false => validators,
+    };
+    if let Some(resp) = validators.not_modified_response(headers) {
+        return Ok(resp);
+    }
+
+    file.seek(io::SeekFrom::Start(page.data_offset))
+        .with_context(ctx)?;
+    let file = file.take(page.compressed_size);
+
+    let (body, len) = if passthrough {
+        (
+            stream_body(file, page.compressed_size),
+            page.compressed_size,
+        )
+    } else {
+        let file = Verified::new(
+            DeflateDecoder::new(file),
+            page.uncompressed_size,
+            page.crc32,
+        );
+        (
+            stream_body(file, page.uncompressed_size),
+            page.uncompressed_size,
+        )
+    };
+
+    let mut resp = Response::new(body);
+    let resp_headers = resp.headers_mut();
+    resp_headers.insert(CONTENT_LENGTH, len.into());
+    set_content_type(resp_headers, page.mime);
+    if passthrough {
+        resp_headers.insert(CONTENT_ENCODING, HeaderValue::from_static("deflate"));
+    }
+    resp_headers.insert(ACCEPT_RANGES, HeaderValue::from_static("none"));
+    resp_headers.insert(VARY, HeaderValue::from_static("accept-encoding"));
+    validators.apply(resp_headers);
+    Ok(resp)
+}
+
+/// Serves a page of an archive that is extracted to be read.
+///
+/// Solid archives are extracted whole and cached, since extracting a single page
+/// decompresses all the ones before it anyway.
+#[cfg(any(feature = "rar", feature = "7z", feature = "tar"))]
+fn serve_extracted<E: ArchiveEntry>(
+    headers: &HeaderMap,
+    cache: &ExtractCache,
+    path: &Path,
+    solid: bool,
+    pages: &[E],
+    pg: usize,
+    extract: impl Fn(&Path, &[E]) -> anyhow::Result<Vec<Vec<u8>>>,
+) -> Result<Response, Error> {
+    let ctx = || format!("{:?}: error opening page", path);
+
+    let page = pages.get(pg).ok_or(Error::NOT_FOUND)?;
+    let modified = fs::metadata(path).and_then(|v| v.modified()).ok();
+    let validators = Validators::new((path, page, modified), modified);
+    if let Some(resp) = validators.not_modified_response(headers) {
+        return Ok(resp);
+    }
+
+    let data = if solid {
+        extract_page(cache, path, pages, pg, extract)
+    } else {
+        extract(path, slice::from_ref(page)).map(|mut v| Bytes::from(v.remove(0)))
+    }
+    .with_context(ctx)?;
+
+    Ok(serve_slice(
+        headers,
+        io::Cursor::new(data),
+        0,
+        page.size(),
+        page.mime(),
+        &validators,
+        None,
+    )
+    .with_context(ctx)?)
+}
+
+/// Extracts a page of a solid archive.
+///
+/// The whole chapter is extracted and cached if it fits in the cache,
+/// otherwise only the page is extracted.
+#[cfg(any(feature = "rar", feature = "7z", feature = "tar"))]
+fn extract_page<E: ArchiveEntry>(
+    cache: &ExtractCache,
+    path: &Path,
+    pages: &[E],
+    pg: usize,
+    extract: impl Fn(&Path, &[E]) -> anyhow::Result<Vec<Vec<u8>>>,
+) -> anyhow::Result<Bytes> {
+    let modified = fs::metadata(path).and_then(|v| v.modified()).ok();
+    let size = pages.iter().map(ArchiveEntry::size).sum();
+    let chapter = cache.get(path, None, modified, size, || {
+        Ok(extract(path, pages)?.into_iter().map(Bytes::from).collect())
+    })?;
+    Ok(match chapter {
+        Some(chapter) => chapter[pg].clone(),
+        None => Bytes::from(extract(path, slice::from_ref(&pages[pg]))?.remove(0)),
+    })
+}
+
+/// Serves an entry of a plain tar archive, read in place.
+#[cfg(feature = "tar")]
+fn serve_tar_entry(headers: &HeaderMap, path: &Path, page: &TarEntry) -> Result<Response, Error> {
+    let ctx = || format!("{:?}: error opening page", path);
+
+    let file = File::open(path).with_context(ctx)?;
+    let modified = file.metadata().and_then(|v| v.modified()).ok();
+    let validators = Validators::new((path, page, modified), modified);
+
+    Ok(serve_slice(
+        headers,
+        file,
+        page.data_offset,
+        page.size,
+        page.mime,
+        &validators,
+        None,
+    )
+    .with_context(ctx)?)
+}
+
+/// Serves a page of a PDF file.
+///
+/// JPEG and JPEG 2000 images filling a page are served in place, other pages
+/// are converted to PNG and cached.
+#[cfg(feature = "pdf")]
+fn serve_pdf_page(
+    headers: &HeaderMap,
+    cache: &ExtractCache,
+    path: &Path,
+    page: &PdfPage,
+) -> Result<Response, Error> {
+    let ctx = || format!("{:?}: error opening page", path);
+
+    let file = File::open(path).with_context(ctx)?;
+    let modified = file.metadata().and_then(|v| v.modified()).ok();
+    let validators = Validators::new((path, page.index, modified), modified);
+
+    if let Some(image) = page.stored_image() {
+        return Ok(serve_slice(
+            headers,
+            file,
+            image.data_offset,
+            image.len,
+            page.mime(),
+            &validators,
+            None,
+        )
+        .with_context(ctx)?);
+    }
+
+    if let Some(resp) = validators.not_modified_response(headers) {
+        return Ok(resp);
+    }
+    let index = Some(page.index as usize);
+    let data = cache
+        .get(path, index, modified, page.pixels_size(), || {
+            Ok(vec![pdf::read_page(path, page)?.into()])
+        })
+        .and_then(|cached| match cached {
+            Some(cached) => Ok(cached[0].clone()),
+            None => Ok(pdf::read_page(path, page)?.into()),
+        })
+        .with_context(ctx)?;
+    let len = data.len() as u64;
+
+    Ok(serve_slice(
+        headers,
+        io::Cursor::new(data),
+        0,
+        len,
+        page.mime(),
+        &validators,
+        None,
+    )
+    .with_context(ctx)?)
+}
+
+/// Serves a whole file, honoring range requests.
+fn serve_file(headers: &HeaderMap, page: &FilePage) -> anyhow::Result<Response> {
+    let file = File::open(&page.path)?;
+    let meta = file.metadata()?;
+    let modified = meta.modified().ok();
+    let validators = Validators::new((&page.path, meta.len(), modified), modified);
+    serve_slice(headers, file, 0, meta.len(), page.mime, &validators, None)
+}
+
+/// Serves `len` bytes of `file` starting at `offset`, honoring range requests.
+///
+/// If `crc32` is given, it is verified when the whole slice is sent.
+fn serve_slice<R: Read + Seek + Send + 'static>(
+    headers: &HeaderMap,
+    mut file: R,
+    offset: u64,
+    len: u64,
+    mime: &'static str,
+    validators: &Validators,
+    crc32: Option<u32>,
+) -> anyhow::Result<Response> {
+    if let Some(resp) = validators.not_modified_response(headers) {
+        return Ok(resp);
+    }
+
+    let (status, range) = match byte_range(headers, len, validators) {
+        ByteRange::Full => (StatusCode::OK, 0..len),
+        ByteRange::Partial(range) => (StatusCode::PARTIAL_CONTENT, range),
+        ByteRange::Unsatisfiable => {
+            let mut resp = Response::new(Body::empty());
+            *resp.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+            let headers = resp.headers_mut();
+            headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+            headers.insert(
+                CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes */{}", len))?,
+            );
+            return Ok(resp);
+        }
+    };
+
+    let size = range.end - range.start;
+
+    file.seek(io::SeekFrom::Start(offset + range.start))?;
+
+    let body = match crc32 {
+        Some(crc32) if status == StatusCode::OK => {
+            stream_body(Verified::new(file.take(size), size, crc32), size)
+        }
+        _ => stream_body(file, size),
+    };
+
+    let mut resp = Response::new(body);
+    *resp.status_mut() = status;
+    let headers = resp.headers_mut();
+    headers.insert(CONTENT_LENGTH, size.into());
+    set_content_type(headers, mime);
+    headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    validators.apply(headers);
+    if status == StatusCode::PARTIAL_CONTENT {
+        headers.insert(
+            CONTENT_RANGE,
+            HeaderValue::from_str(&format!(
+                "bytes {}-{}/{}",
+                range.start,
+                range.end - 1,
+                len
+            ))?,
+        );
+    }
+
+    Ok(resp)
+}
+
+/// Builds a `401 Unauthorized` response asking for one of the given authentication schemes.
+fn unauthorized(challenges: impl IntoIterator<Item = &'static str>) -> Response {
+    let mut resp = Error::UNAUTHORIZED.into_response();
+    for challenge in challenges {
+        resp.headers_mut()
+            .append(WWW_AUTHENTICATE, HeaderValue::from_static(challenge));
+    }
+    resp
+}
+
+/// Sets the `Content-Type` of an image response, and disables sniffing by clients.
+fn set_content_type(headers: &mut HeaderMap, mime: &'static str) {
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(mime));
+    headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
 }
 
 #[derive(Debug)]
@@ -225,8 +1030,10 @@ impl From<anyhow::Error> for Error {
 }
 
 impl Error {
+    pub const BAD_REQUEST: Self = Self::StatusCode(StatusCode::BAD_REQUEST);
     pub const NOT_FOUND: Self = Self::StatusCode(StatusCode::NOT_FOUND);
     pub const NOT_ACCEPTABLE: Self = Self::StatusCode(StatusCode::NOT_ACCEPTABLE);
+    pub const UNAUTHORIZED: Self = Self::StatusCode(StatusCode::UNAUTHORIZED);
 
     pub fn into_response(self) -> Response<Body> {
         let mut res = Response::new(Body::empty());
@@ -250,46 +1057,85 @@ impl Error {
     }
 }
 
+#[derive(Clone)]
 pub struct JsonBytes {
-    raw: Box<[u8]>,
-    gzip: Option<Box<[u8]>>,
+    raw: Bytes,
+    gzip: Option<Bytes>,
+    validators: Validators,
 }
 
 impl JsonBytes {
     pub fn new(raw: Box<[u8]>) -> Self {
+        Self::with_compression(raw, SystemTime::now(), Compression::best())
+    }
+
+    /// Wraps a response built for a single request, compressing it quickly.
+    ///
+    /// `modified` is when the data it is built from last changed, so conditional requests can match.
+    pub fn dynamic(raw: Box<[u8]>, modified: SystemTime) -> Self {
+        Self::with_compression(raw, modified, Compression::fast())
+    }
+
+    fn with_compression(raw: Box<[u8]>, modified: SystemTime, level: Compression) -> Self {
         use flate2::write::GzEncoder;
-        use flate2::Compression;
+
+        let validators = Validators::new(&raw, Some(modified));
 
         if raw.len() <= 64 {
-            return Self { raw, gzip: None };
+            return Self {
+                raw: raw.into(),
+                gzip: None,
+                validators,
+            };
         }
 
         let gzip = Vec::new();
-        let mut gzip = GzEncoder::new(gzip, Compression::best());
+        let mut gzip = GzEncoder::new(gzip, level);
         gzip.write_all(&raw).expect("Vec::write never fails");
-        let gzip = gzip
-            .finish()
-            .expect("Vec::write never fails")
-            .into_boxed_slice();
-        let gzip = (gzip.len() < raw.len()).then_some(gzip);
-
-        Self { raw, gzip }
-    }
-
-    pub fn to_response(&'static self, headers: &HeaderMap) -> Result<Response, Error> {
-        fn json(v: &'static [u8], enc: Option<&'static str>) -> Response {
-            let mut res = Response::new(v.into());
-            let headers = res.headers_mut();
-            headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-            if let Some(enc) = enc {
-                headers.insert(CONTENT_ENCODING, HeaderValue::from_static(enc));
+        let gzip = gzip.finish().expect("Vec::write never fails");
+        let gzip = (gzip.len() < raw.len()).then(|| gzip.into());
+
+        Self {
+            raw: raw.into(),
+            gzip,
+            validators,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.raw
+    }
+
+    pub fn to_response(&self, headers: &HeaderMap) -> Result<Response, Error> {
+        let json = |v: &Bytes, enc: Option<&'static str>| -> Response {
+            let validators = match enc {
+                Some(enc) => self.validators.with_encoding(enc),
+                None => self.validators.clone(),
+            };
+
+            let mut res = match validators.not_modified_response(headers) {
+                Some(res) => res,
+                None => {
+                    let mut res = Response::new(v.clone().into());
+                    let headers = res.headers_mut();
+                    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+                    if let Some(enc) = enc {
+                        headers.insert(CONTENT_ENCODING, HeaderValue::from_static(enc));
+                    }
+                    validators.apply(headers);
+                    res
+                }
+            };
+            if self.gzip.is_some() {
+                res.headers_mut()
+                    .insert(VARY, HeaderValue::from_static("accept-encoding"));
             }
             res
-        }
+        };
 
         let accept_encoding = match headers.get(ACCEPT_ENCODING) {
             Some(v) => v.to_str().map_err(|_| Error::NOT_ACCEPTABLE)?,
-            None => return Ok(json(self.raw.deref(), None)),
+            None => return Ok(json(&self.raw, None)),
         };
 
         if let (Some(gzip), true) = (&self.gzip, accept_encoding.contains("gzip")) {
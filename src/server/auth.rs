@@ -0,0 +1,225 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use anyhow::Context;
+use argon2::{
+    password_hash::{PasswordHashString, SaltString},
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use http::{header::AUTHORIZATION, HeaderMap};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use tokio::sync::Semaphore;
+
+use super::{blocking, Error};
+
+/// Maximum number of verified Basic credentials remembered,
+/// so argon2 only runs once per client instead of once per request.
+const VERIFIED_CACHE_SIZE: usize = 1024;
+/// Maximum number of passwords verified at once, argon2 using 19 MiB each with the default parameters.
+const CONCURRENT_VERIFICATIONS: usize = 4;
+/// Hash of a random password, verified for unknown users so they take as long as wrong passwords.
+const DUMMY_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$ors7GjxNnbvSZ7EXCeJCtw$iAAUAzuOV4Soh8MSBvoc9a1EN6q5k5rEu00TSHlzSec";
+
+/// Credentials accepted by the server, loaded from a TOML file:
+///
+/// ```toml
+/// # hex encoded SHA-256 digests of accepted bearer tokens
+/// tokens = ["9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"]
+///
+/// # user names and argon2 hashes of their passwords, for HTTP Basic auth
+/// [users]
+/// alice = "$argon2id$v=19$m=19456,t=2,p=1$..."
+/// ```
+#[derive(Debug)]
+pub struct Auth {
+    users: HashMap<String, PasswordHashString>,
+    tokens: Vec<[u8; 32]>,
+    /// SHA-256 digests of `Authorization` values that passed verification.
+    verified: Mutex<HashSet<[u8; 32]>>,
+    verifications: Semaphore,
+}
+
+impl Auth {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct AuthFile {
+            #[serde(default)]
+            users: HashMap<String, String>,
+            #[serde(default)]
+            tokens: Vec<String>,
+        }
+
+        let ctx = || format!("{:?}: error loading credentials", path);
+        let file: AuthFile =
+            toml::from_slice(&fs::read(path).with_context(ctx)?).with_context(ctx)?;
+
+        let users = file
+            .users
+            .into_iter()
+            .map(|(user, hash)| {
+                let hash = PasswordHashString::new(&hash)
+                    .map_err(|e| anyhow::anyhow!("invalid password hash for {:?}: {}", user, e))
+                    .with_context(ctx)?;
+                Ok((user, hash))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let tokens = file
+            .tokens
+            .iter()
+            .map(|token| {
+                parse_sha256(token)
+                    .with_context(|| format!("invalid token digest {:?}", token))
+                    .with_context(ctx)
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            users,
+            tokens,
+            verified: Mutex::new(HashSet::new()),
+            verifications: Semaphore::new(CONCURRENT_VERIFICATIONS),
+        })
+    }
+
+    /// The `WWW-Authenticate` challenges sent with `401 Unauthorized` responses.
+    pub fn challenges(&self) -> impl Iterator<Item = &'static str> {
+        let basic =
+            (!self.users.is_empty()).then_some("Basic realm=\"tachi-remote\", charset=\"UTF-8\"");
+        let bearer = (!self.tokens.is_empty()).then_some("Bearer realm=\"tachi-remote\"");
+        basic.into_iter().chain(bearer)
+    }
+
+    /// Checks the `Authorization` header of a request.
+    pub async fn is_authorized(&'static self, headers: &HeaderMap) -> Result<bool, Error> {
+        let Some(value) = headers.get(AUTHORIZATION).and_then(|v| v.to_str().ok()) else {
+            return Ok(false);
+        };
+        let Some((scheme, credentials)) = value.trim().split_once(' ') else {
+            return Ok(false);
+        };
+        let credentials = credentials.trim();
+
+        if scheme.eq_ignore_ascii_case("bearer") {
+            let digest: [u8; 32] = Sha256::digest(credentials).into();
+            let matched = self
+                .tokens
+                .iter()
+                .fold(subtle::Choice::from(0), |acc, token| {
+                    acc | token.ct_eq(&digest)
+                });
+            return Ok(matched.into());
+        }
+
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return Ok(false);
+        }
+
+        let digest: [u8; 32] = Sha256::digest(credentials).into();
+        if self.verified().contains(&digest) {
+            return Ok(true);
+        }
+
+        let Some((user, password)) = BASE64
+            .decode(credentials)
+            .ok()
+            .and_then(|v| String::from_utf8(v).ok())
+            .and_then(|v| {
+                let (user, password) = v.split_once(':')?;
+                Some((user.to_owned(), password.to_owned()))
+            })
+        else {
+            return Ok(false);
+        };
+        let (hash, known) = match self.users.get(&user) {
+            Some(hash) => (hash.password_hash(), true),
+            None => (
+                PasswordHash::new(DUMMY_HASH).expect("dummy hash is valid"),
+                false,
+            ),
+        };
+
+        let _permit = self
+            .verifications
+            .acquire()
+            .await
+            .context("server closed")?;
+        // argon2 is slow on purpose, keep it off the async workers
+        let verified = blocking(move || {
+            let verified = Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok();
+            Ok(verified && known)
+        })
+        .await?;
+
+        if verified {
+            let mut cache = self.verified();
+            if cache.len() >= VERIFIED_CACHE_SIZE {
+                cache.clear();
+            }
+            cache.insert(digest);
+        }
+        Ok(verified)
+    }
+
+    fn verified(&self) -> MutexGuard<'_, HashSet<[u8; 32]>> {
+        self.verified.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Hashes a password for the `[users]` table of the credentials file.
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut rand_core::OsRng);
+    let hash: PasswordHash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("error hashing password: {}", e))?;
+    Ok(hash.to_string())
+}
+
+fn parse_sha256(hex: &str) -> anyhow::Result<[u8; 32]> {
+    let hex = hex.trim();
+    anyhow::ensure!(
+        hex.len() == 64 && hex.bytes().all(|v| v.is_ascii_hexdigit()),
+        "expected 64 hex digits"
+    );
+
+    let mut digest = [0; 32];
+    for (byte, chunk) in digest.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        let chunk = std::str::from_utf8(chunk).expect("hex is ascii");
+        *byte = u8::from_str_radix(chunk, 16).expect("hex digits");
+    }
+    Ok(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256_digests() {
+        let hex = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+        let digest = parse_sha256(hex).unwrap();
+        assert_eq!(digest[..4], [0x9f, 0x86, 0xd0, 0x81]);
+        assert_eq!(digest[31], 0x08);
+        assert_eq!(
+            parse_sha256(&format!(" {}\n", hex.to_uppercase())).unwrap(),
+            digest
+        );
+
+        assert!(parse_sha256(&hex[1..]).is_err());
+        assert!(parse_sha256(&format!("{}00", hex)).is_err());
+        assert!(parse_sha256(&format!("{}zz", &hex[2..])).is_err());
+        assert!(parse_sha256(&format!("{}+f", &hex[2..])).is_err());
+        assert!(parse_sha256(&format!("{}é", &hex[2..])).is_err());
+    }
+}
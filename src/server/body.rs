@@ -0,0 +1,117 @@
+use std::io::{self, Read};
+
+use bytes::Bytes;
+use flate2::Crc;
+use hyper::Body;
+
+/// Maximum size of the chunks read when streaming a body.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Streams exactly `len` bytes from a blocking reader as a response body.
+///
+/// Each chunk is read on the blocking thread pool, so only one chunk per
+/// response is kept in memory. The body fails if the reader ends early.
+pub fn stream_body<R: Read + Send + 'static>(reader: R, len: u64) -> Body {
+    let stream = futures::stream::try_unfold(
+        (reader.take(len), len),
+        |(mut reader, remaining)| async move {
+            if remaining == 0 {
+                return Ok::<_, io::Error>(None);
+            }
+
+            tokio::task::spawn_blocking(move || {
+                let mut buf = vec![0; CHUNK_SIZE.min(remaining.try_into().unwrap_or(usize::MAX))];
+                let read = loop {
+                    match reader.read(&mut buf) {
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        res => break res?,
+                    }
+                };
+                if read == 0 {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+                }
+
+                buf.truncate(read);
+                Ok(Some((Bytes::from(buf), (reader, remaining - read as u64))))
+            })
+            .await
+            .map_err(io::Error::other)?
+        },
+    );
+
+    Body::wrap_stream(stream)
+}
+
+/// A reader that checks the size and CRC-32 of the data read through it.
+///
+/// Reading fails as soon as more than `len` bytes are produced, and the
+/// checksum is verified before the last bytes are returned, so corrupt data
+/// is never sent in full.
+pub struct Verified<R> {
+    inner: R,
+    remaining: u64,
+    crc: Crc,
+    expected_crc: u32,
+}
+
+impl<R: Read> Verified<R> {
+    pub fn new(inner: R, len: u64, crc32: u32) -> Self {
+        Self {
+            inner,
+            remaining: len,
+            crc: Crc::new(),
+            expected_crc: crc32,
+        }
+    }
+}
+
+impl<R: Read> Read for Verified<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let max = buf.len().min(
+            self.remaining
+                .saturating_add(1)
+                .try_into()
+                .unwrap_or(usize::MAX),
+        );
+        let read = self.inner.read(&mut buf[..max])?;
+
+        if read as u64 > self.remaining {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "data is larger than its declared size",
+            ));
+        }
+        if read == 0 && self.remaining > 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        self.remaining -= read as u64;
+        self.crc.update(&buf[..read]);
+
+        if self.remaining == 0 && read > 0 {
+            let mut extra = [0];
+            if self.inner.read(&mut extra)? != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "data is larger than its declared size",
+                ));
+            }
+            if self.crc.sum() != self.expected_crc {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "crc32 mismatch, expected {:08x}, got {:08x}",
+                        self.expected_crc,
+                        self.crc.sum()
+                    ),
+                ));
+            }
+        }
+
+        Ok(read)
+    }
+}
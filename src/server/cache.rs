@@ -0,0 +1,302 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    time::SystemTime,
+};
+
+use http::{
+    header::{CACHE_CONTROL, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    HeaderMap, HeaderValue, StatusCode,
+};
+use httpdate::HttpDate;
+use hyper::Body;
+
+use super::Response;
+
+/// `Cache-Control` values sent for each type of route.
+///
+/// `None` means no `Cache-Control` header is sent.
+#[derive(Debug, Clone)]
+pub struct CachePolicy {
+    pub library: Option<HeaderValue>,
+    pub manga: Option<HeaderValue>,
+    pub cover: Option<HeaderValue>,
+    pub page: Option<HeaderValue>,
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        Self {
+            library: Some(HeaderValue::from_static("no-cache")),
+            manga: Some(HeaderValue::from_static("no-cache")),
+            cover: Some(HeaderValue::from_static("no-cache")),
+            page: Some(HeaderValue::from_static("max-age=86400")),
+        }
+    }
+}
+
+impl CachePolicy {
+    /// The policy of each route type, by name.
+    pub fn routes(&self) -> [(&'static str, &Option<HeaderValue>); 4] {
+        [
+            ("library", &self.library),
+            ("manga", &self.manga),
+            ("cover", &self.cover),
+            ("page", &self.page),
+        ]
+    }
+
+    /// Sets the policy of a route type from a `<route>=<value>` string.
+    ///
+    /// An empty value disables the `Cache-Control` header for that route type.
+    pub fn set(&mut self, spec: &str) -> Result<(), String> {
+        let (route, value) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("invalid cache policy {:?}, expected <route>=<value>", spec))?;
+
+        let policy = match route {
+            "library" => &mut self.library,
+            "manga" => &mut self.manga,
+            "cover" => &mut self.cover,
+            "page" => &mut self.page,
+            _ => return Err(format!("unknown route type {:?}", route)),
+        };
+
+        *policy = match value {
+            "" => None,
+            value => Some(
+                HeaderValue::from_str(value)
+                    .map_err(|_| format!("invalid Cache-Control value {:?}", value))?,
+            ),
+        };
+
+        Ok(())
+    }
+}
+
+/// Sets or removes the `Cache-Control` header of `resp`.
+///
+/// Only successful and `304 Not Modified` responses get the policy,
+/// so errors such as `416 Range Not Satisfiable` are never cached.
+pub fn set_cache_control(resp: &mut Response, value: &Option<HeaderValue>) {
+    let cacheable = matches!(
+        resp.status(),
+        StatusCode::OK | StatusCode::PARTIAL_CONTENT | StatusCode::NOT_MODIFIED
+    );
+    let headers = resp.headers_mut();
+    match value {
+        Some(value) if cacheable => headers.insert(CACHE_CONTROL, value.clone()),
+        _ => headers.remove(CACHE_CONTROL),
+    };
+}
+
+/// Validators of a response body, used for conditional requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Validators {
+    etag: String,
+    modified: Option<HttpDate>,
+}
+
+impl Validators {
+    /// Creates validators with a strong ETag derived from `identity`.
+    ///
+    /// `identity` must change whenever the body changes, e.g. the body itself,
+    /// or the path, size and modification time of a file.
+    pub fn new(identity: impl Hash, modified: Option<SystemTime>) -> Self {
+        let mut hasher = DefaultHasher::new();
+        identity.hash(&mut hasher);
+
+        Self {
+            etag: format!("{:016x}", hasher.finish()),
+            modified: modified.map(HttpDate::from),
+        }
+    }
+
+    /// Returns the validators of the same body sent with a content encoding.
+    pub fn with_encoding(&self, encoding: &str) -> Self {
+        Self {
+            etag: format!("{}-{}", self.etag, encoding),
+            modified: self.modified,
+        }
+    }
+
+    /// Checks if an `If-Range` value matches these validators.
+    ///
+    /// ETags are compared strongly, and dates must match exactly.
+    pub fn if_range_matches(&self, if_range: &str) -> bool {
+        let if_range = if_range.trim();
+        if if_range.starts_with('"') {
+            if_range.strip_prefix('"').and_then(|v| v.strip_suffix('"')) == Some(&self.etag)
+        } else if if_range.starts_with("W/") {
+            false
+        } else {
+            let Some(modified) = self.modified else { return false };
+            if_range
+                .parse::<HttpDate>()
+                .is_ok_and(|date| date == modified)
+        }
+    }
+
+    /// Evaluates `If-None-Match` and `If-Modified-Since`,
+    /// returning `true` if the client's copy is still fresh.
+    pub fn is_not_modified(&self, headers: &HeaderMap) -> bool {
+        if let Some(if_none_match) = headers.get(IF_NONE_MATCH) {
+            let Ok(if_none_match) = if_none_match.to_str() else { return false };
+            return if_none_match.trim() == "*"
+                || entity_tags(if_none_match).any(|tag| tag == self.etag);
+        }
+
+        let (Some(modified), Some(since)) = (self.modified, headers.get(IF_MODIFIED_SINCE))
+        else {
+            return false;
+        };
+
+        since
+            .to_str()
+            .ok()
+            .and_then(|v| v.parse::<HttpDate>().ok())
+            .is_some_and(|since| modified <= since)
+    }
+
+    /// Adds `ETag` and `Last-Modified` headers.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        headers.insert(
+            ETAG,
+            HeaderValue::from_str(&format!("\"{}\"", self.etag)).expect("etag is hex"),
+        );
+        if let Some(modified) = self.modified {
+            headers.insert(
+                LAST_MODIFIED,
+                HeaderValue::from_str(&modified.to_string()).expect("http date is ascii"),
+            );
+        }
+    }
+
+    /// Returns a `304 Not Modified` response if the client's copy is still fresh.
+    pub fn not_modified_response(&self, headers: &HeaderMap) -> Option<Response> {
+        if !self.is_not_modified(headers) {
+            return None;
+        }
+
+        let mut resp = Response::new(Body::empty());
+        *resp.status_mut() = StatusCode::NOT_MODIFIED;
+        self.apply(resp.headers_mut());
+        Some(resp)
+    }
+}
+
+/// Iterates over the opaque tags of a list of entity tags, ignoring weakness.
+fn entity_tags(list: &str) -> impl Iterator<Item = &str> {
+    let mut rest = list;
+    std::iter::from_fn(move || {
+        let start = rest.find('"')?;
+        let tag = &rest[start + 1..];
+        let end = tag.find('"')?;
+        rest = &tag[end + 1..];
+        Some(&tag[..end])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    const DATE: &str = "Sat, 10 Jan 2004 13:37:04 GMT";
+
+    fn validators() -> Validators {
+        Validators::new("body", Some(UNIX_EPOCH + Duration::from_secs(1 << 30)))
+    }
+
+    fn headers(name: http::header::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn cache_policy() {
+        let mut policy = CachePolicy::default();
+        policy.set("page=max-age=604800").unwrap();
+        assert_eq!(policy.page.as_ref().unwrap(), "max-age=604800");
+        policy.set("library=").unwrap();
+        assert_eq!(policy.library, None);
+
+        assert!(policy.set("page").is_err());
+        assert!(policy.set("chapter=no-cache").is_err());
+        assert!(policy.set("page=a\nb").is_err());
+    }
+
+    #[test]
+    fn cache_control_only_on_success() {
+        let value = Some(HeaderValue::from_static("max-age=86400"));
+        for (status, cached) in [
+            (StatusCode::OK, true),
+            (StatusCode::PARTIAL_CONTENT, true),
+            (StatusCode::NOT_MODIFIED, true),
+            (StatusCode::RANGE_NOT_SATISFIABLE, false),
+            (StatusCode::NOT_FOUND, false),
+        ] {
+            let mut resp = Response::new(Body::empty());
+            *resp.status_mut() = status;
+            set_cache_control(&mut resp, &value);
+            assert_eq!(resp.headers().contains_key(CACHE_CONTROL), cached);
+        }
+    }
+
+    #[test]
+    fn etags() {
+        let v = validators();
+        assert_eq!(
+            v,
+            Validators::new("body", Some(UNIX_EPOCH + Duration::from_secs(1 << 30)))
+        );
+        assert_ne!(v.etag, Validators::new("other", None).etag);
+        assert_eq!(v.with_encoding("gzip").etag, format!("{}-gzip", v.etag));
+
+        let mut headers = HeaderMap::new();
+        v.apply(&mut headers);
+        assert_eq!(headers[ETAG], format!("\"{}\"", v.etag));
+        assert_eq!(headers[LAST_MODIFIED], DATE);
+    }
+
+    #[test]
+    fn if_range() {
+        let v = validators();
+        assert!(v.if_range_matches(&format!(" \"{}\" ", v.etag)));
+        assert!(v.if_range_matches(DATE));
+        assert!(!v.if_range_matches(&format!("W/\"{}\"", v.etag)));
+        assert!(!v.if_range_matches("\"other\""));
+        assert!(!v.if_range_matches("Sat, 10 Jan 2004 13:37:05 GMT"));
+        assert!(!Validators::new("body", None).if_range_matches(DATE));
+    }
+
+    #[test]
+    fn not_modified() {
+        let v = validators();
+        let tag = format!("\"{}\"", v.etag);
+
+        assert!(!v.is_not_modified(&HeaderMap::new()));
+        assert!(v.is_not_modified(&headers(IF_NONE_MATCH, &tag)));
+        assert!(v.is_not_modified(&headers(IF_NONE_MATCH, &format!("\"a\", W/{}", tag))));
+        assert!(v.is_not_modified(&headers(IF_NONE_MATCH, "*")));
+        assert!(!v.is_not_modified(&headers(IF_NONE_MATCH, "\"a\"")));
+
+        assert!(v.is_not_modified(&headers(IF_MODIFIED_SINCE, DATE)));
+        assert!(v.is_not_modified(&headers(IF_MODIFIED_SINCE, "Sun, 11 Jan 2004 00:00:00 GMT")));
+        assert!(!v.is_not_modified(&headers(IF_MODIFIED_SINCE, "Fri, 09 Jan 2004 00:00:00 GMT")));
+        assert!(!v.is_not_modified(&headers(IF_MODIFIED_SINCE, "yesterday")));
+
+        // If-None-Match takes precedence
+        let mut both = headers(IF_NONE_MATCH, "\"a\"");
+        both.insert(IF_MODIFIED_SINCE, HeaderValue::from_static(DATE));
+        assert!(!v.is_not_modified(&both));
+        assert_eq!(
+            v.not_modified_response(&headers(IF_NONE_MATCH, &tag))
+                .unwrap()
+                .status(),
+            StatusCode::NOT_MODIFIED
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::SystemTime,
+};
+
+use bytes::Bytes;
+
+/// The pages of a chapter, or `None` until they are extracted.
+type Slot = Arc<Mutex<Option<Arc<[Bytes]>>>>;
+
+/// Chapters extracted from archives that can only be read from the start, and pages
+/// converted to be served, kept in memory.
+///
+/// Solid archives compress all their files as one stream, so reading a page means
+/// decompressing every page before it. Extracting the whole chapter once makes
+/// reading it page by page linear instead of quadratic.
+///
+/// Chapters are counted against the limit from the moment their extraction starts,
+/// so concurrent extractions can't exceed it either.
+#[derive(Debug)]
+pub struct ExtractCache {
+    max_size: u64,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Total size of the cached and in-flight chapters.
+    size: u64,
+    /// Least recently used first.
+    entries: VecDeque<Entry>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    path: PathBuf,
+    page: Option<usize>,
+    modified: Option<SystemTime>,
+    size: u64,
+    /// Whether the pages are in the slot, so the entry can be evicted.
+    extracted: bool,
+    slot: Slot,
+}
+
+impl ExtractCache {
+    pub fn new(max_size: u64) -> Self {
+        Self {
+            max_size,
+            inner: Mutex::default(),
+        }
+    }
+
+    /// Returns the pages of the archive at `path`, calling `extract` if they aren't cached.
+    ///
+    /// `page` is set for pages converted one at a time, `extract` then returning only that page.
+    /// `size` is the total size of the pages. Returns `None` if they don't fit in the cache,
+    /// either on their own or next to the chapters being extracted, in which case pages
+    /// should be extracted one at a time instead.
+    ///
+    /// Concurrent requests for the same archive wait for a single extraction.
+    pub fn get(
+        &self,
+        path: &Path,
+        page: Option<usize>,
+        modified: Option<SystemTime>,
+        size: u64,
+        extract: impl FnOnce() -> anyhow::Result<Vec<Bytes>>,
+    ) -> anyhow::Result<Option<Arc<[Bytes]>>> {
+        if size > self.max_size {
+            return Ok(None);
+        }
+
+        let slot = {
+            let mut inner = self.inner();
+            let pos = inner
+                .entries
+                .iter()
+                .position(|v| v.path == path && v.page == page && v.modified == modified);
+            let entry = match pos {
+                Some(pos) => inner.entries.remove(pos).expect("position is valid"),
+                None => {
+                    while inner.size + size > self.max_size {
+                        let Some(pos) = inner.entries.iter().position(|v| v.extracted) else {
+                            return Ok(None);
+                        };
+                        let evicted = inner.entries.remove(pos).expect("position is valid");
+                        inner.size -= evicted.size;
+                    }
+                    inner.size += size;
+                    Entry {
+                        path: path.to_owned(),
+                        page,
+                        modified,
+                        size,
+                        extracted: false,
+                        slot: Slot::default(),
+                    }
+                }
+            };
+            let slot = entry.slot.clone();
+            inner.entries.push_back(entry);
+            slot
+        };
+
+        let mut pages = slot.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(pages) = &*pages {
+            return Ok(Some(pages.clone()));
+        }
+        if !self
+            .inner()
+            .entries
+            .iter()
+            .any(|v| Arc::ptr_eq(&v.slot, &slot))
+        {
+            // the extraction we waited for failed and gave its room back
+            return Ok(None);
+        }
+
+        let res = extract();
+
+        let mut inner = self.inner();
+        let pos = inner
+            .entries
+            .iter()
+            .position(|v| Arc::ptr_eq(&v.slot, &slot));
+        match res {
+            Ok(extracted) => {
+                let extracted: Arc<[Bytes]> = extracted.into();
+                *pages = Some(extracted.clone());
+                if let Some(pos) = pos {
+                    let actual = extracted.iter().map(|v| v.len() as u64).sum();
+                    let entry = &mut inner.entries[pos];
+                    let reserved = entry.size;
+                    entry.size = actual;
+                    entry.extracted = true;
+                    inner.size = inner.size - reserved + actual;
+                }
+                Ok(Some(extracted))
+            }
+            Err(e) => {
+                if let Some(pos) = pos {
+                    let failed = inner.entries.remove(pos).expect("position is valid");
+                    inner.size -= failed.size;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn inner(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
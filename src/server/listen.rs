@@ -0,0 +1,292 @@
+#[cfg(unix)]
+use std::{
+    env, fs, io,
+    os::unix::{
+        fs::FileTypeExt,
+        io::{FromRawFd, IntoRawFd, RawFd},
+        net::{UnixListener, UnixStream},
+    },
+    path::PathBuf,
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+use std::{
+    fmt::{self, Display},
+    net::{SocketAddr, TcpListener},
+    str::FromStr,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+use {
+    hyper::server::accept::{self, Accept},
+    log::error,
+};
+
+/// An address to accept connections on.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Listen {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// Sockets passed by the service manager, see `sd_listen_fds(3)`.
+    #[cfg(unix)]
+    Systemd,
+}
+
+impl FromStr for Listen {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        #[cfg(unix)]
+        if s == "systemd" {
+            return Ok(Self::Systemd);
+        }
+        #[cfg(unix)]
+        if let Some(path) = s.strip_prefix("unix:") {
+            return Ok(Self::Unix(path.into()));
+        }
+
+        s.parse()
+            .map(Self::Tcp)
+            .map_err(|_| format!("invalid listen address {:?}", s))
+    }
+}
+
+impl TryFrom<String> for Listen {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Listen> for String {
+    fn from(v: Listen) -> Self {
+        v.to_string()
+    }
+}
+
+impl Display for Listen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => Display::fmt(addr, f),
+            #[cfg(unix)]
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+            #[cfg(unix)]
+            Self::Systemd => f.write_str("systemd"),
+        }
+    }
+}
+
+/// A bound socket, ready to accept connections.
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(tcp) => match tcp.local_addr() {
+                Ok(addr) => Display::fmt(&addr, f),
+                Err(_) => f.write_str("<unknown>"),
+            },
+            #[cfg(unix)]
+            Self::Unix(unix) => match unix
+                .local_addr()
+                .ok()
+                .as_ref()
+                .and_then(|v| v.as_pathname())
+            {
+                Some(path) => write!(f, "unix:{}", path.display()),
+                None => f.write_str("unix:<unnamed>"),
+            },
+        }
+    }
+}
+
+impl Listen {
+    pub fn bind(&self) -> anyhow::Result<Vec<Listener>> {
+        let listeners = match self {
+            Self::Tcp(addr) => vec![Listener::Tcp(TcpListener::bind(addr)?)],
+            #[cfg(unix)]
+            Self::Unix(path) => {
+                // a socket left behind by a previous run would make binding fail,
+                // but one that still accepts connections belongs to a running server
+                if fs::symlink_metadata(path).is_ok_and(|v| v.file_type().is_socket()) {
+                    match UnixStream::connect(path) {
+                        Ok(_) => anyhow::bail!("{:?} is in use by another server", path),
+                        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                            fs::remove_file(path)?
+                        }
+                        Err(_) => {}
+                    }
+                }
+                vec![Listener::Unix(UnixListener::bind(path)?)]
+            }
+            #[cfg(unix)]
+            Self::Systemd => systemd_listeners()?,
+        };
+
+        Ok(listeners)
+    }
+}
+
+/// Number of sockets passed by the service manager, or why there are none.
+#[cfg(unix)]
+static LISTEN_FDS: Mutex<Option<Result<RawFd, String>>> = Mutex::new(None);
+
+/// Takes the `LISTEN_FDS` protocol variables out of the environment,
+/// so the sockets aren't passed on to child processes.
+///
+/// Changing the environment isn't thread-safe, so this must run before any thread is spawned.
+#[cfg(unix)]
+pub fn take_listen_fds() {
+    let fds = (|| {
+        let pid = env::var("LISTEN_PID").context("LISTEN_PID is not set")?;
+        anyhow::ensure!(
+            pid.parse() == Ok(std::process::id()),
+            "LISTEN_PID is set for another process"
+        );
+        env::var("LISTEN_FDS")
+            .context("LISTEN_FDS is not set")?
+            .parse()
+            .context("invalid LISTEN_FDS")
+    })();
+
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    *LISTEN_FDS.lock().unwrap_or_else(PoisonError::into_inner) =
+        Some(fds.map_err(|e| format!("{:#}", e)));
+}
+
+/// Takes the listening sockets passed with the `LISTEN_FDS` protocol.
+#[cfg(unix)]
+fn systemd_listeners() -> anyhow::Result<Vec<Listener>> {
+    /// The first file descriptor passed by the service manager.
+    const SD_LISTEN_FDS_START: RawFd = 3;
+
+    let fds = LISTEN_FDS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take()
+        .context("the sockets passed by the service manager were already taken")?
+        .map_err(anyhow::Error::msg)?;
+
+    let end = SD_LISTEN_FDS_START
+        .checked_add(fds)
+        .with_context(|| format!("invalid LISTEN_FDS {}", fds))?;
+    (SD_LISTEN_FDS_START..end)
+        .map(|fd| {
+            anyhow::ensure!(
+                socket_option(fd, libc::SO_TYPE)? == libc::SOCK_STREAM
+                    && socket_option(fd, libc::SO_ACCEPTCONN)? != 0,
+                "file descriptor {} is not a listening stream socket",
+                fd
+            );
+
+            // SAFETY: the service manager passes ownership of these descriptors to us,
+            // and they are only taken once since `LISTEN_FDS` is emptied
+            let tcp = unsafe { TcpListener::from_raw_fd(fd) };
+            if tcp.local_addr().is_ok() {
+                return Ok(Listener::Tcp(tcp));
+            }
+
+            // SAFETY: as above, ownership moves from the TcpListener
+            let unix = unsafe { UnixListener::from_raw_fd(tcp.into_raw_fd()) };
+            unix.local_addr()
+                .with_context(|| format!("file descriptor {} is not a TCP or Unix socket", fd))?;
+            Ok(Listener::Unix(unix))
+        })
+        .collect()
+}
+
+/// Reads an integer `SOL_SOCKET` option of a socket.
+#[cfg(unix)]
+fn socket_option(fd: RawFd, option: libc::c_int) -> io::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: the option is read into an int of the given size
+    let res = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            option,
+            (&mut value as *mut libc::c_int).cast(),
+            &mut len,
+        )
+    };
+    if res == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+/// Accepts connections on a Unix socket.
+#[cfg(unix)]
+pub fn unix_incoming(
+    unix: tokio::net::UnixListener,
+) -> impl Accept<Conn = tokio::net::UnixStream, Error = io::Error> {
+    accept::from_stream(futures::stream::unfold(unix, |unix| async move {
+        loop {
+            match unix.accept().await {
+                Ok((stream, _)) => return Some((Ok(stream), unix)),
+                Err(e) => {
+                    // e.g. too many open files, give the server time to close some
+                    error!("error accepting connection: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "127.0.0.1:8080".parse(),
+            Ok(Listen::Tcp(SocketAddr::from(([127, 0, 0, 1], 8080))))
+        );
+        assert_eq!(
+            "[::1]:8080".parse(),
+            Ok(Listen::Tcp(SocketAddr::from((
+                [0, 0, 0, 0, 0, 0, 0, 1],
+                8080
+            ))))
+        );
+        assert!("localhost:8080".parse::<Listen>().is_err());
+        assert!("8080".parse::<Listen>().is_err());
+        assert!("127.0.0.1".parse::<Listen>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parse_unix() {
+        assert_eq!("systemd".parse(), Ok(Listen::Systemd));
+        assert_eq!(
+            "unix:/run/tachi-remote.sock".parse(),
+            Ok(Listen::Unix("/run/tachi-remote.sock".into()))
+        );
+        for s in ["systemd", "unix:/run/tachi-remote.sock"] {
+            assert_eq!(s.parse::<Listen>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for s in ["127.0.0.1:8080", "[::1]:8080"] {
+            assert_eq!(s.parse::<Listen>().unwrap().to_string(), s);
+        }
+    }
+}
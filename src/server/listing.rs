@@ -0,0 +1,149 @@
+use serde::Deserialize;
+
+use crate::{
+    load::{LibraryEntry, MangaEntry, MangaStatus},
+    search::{normalize, normalize_list},
+};
+
+/// Filters and sort order of a library listing, from the query string.
+///
+/// Lists are comma separated, text is matched ignoring case and accents.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ListingQuery {
+    /// Statuses to include, by name or number.
+    status: Option<String>,
+    /// Tags that must all be present.
+    tags: Option<String>,
+    /// Tags that must not be present.
+    exclude_tags: Option<String>,
+    /// Text that must be part of the authors.
+    author: Option<String>,
+    /// Text that must be part of the artists.
+    artist: Option<String>,
+    sort: Sort,
+    /// Defaults to ascending for titles, and newest first for dates.
+    order: Option<Order>,
+    /// Leaves out manga without chapters.
+    #[serde(skip)]
+    with_chapters: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Sort {
+    /// The order the manga were found in.
+    #[default]
+    Library,
+    Title,
+    /// When the manga directory was created.
+    Added,
+    /// The date of the newest chapter, see [`MangaEntry::latest_chapter`].
+    Latest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Order {
+    Asc,
+    Desc,
+}
+
+impl ListingQuery {
+    /// Lists the manga with the newest chapters first, for Tachiyomi's "Latest" tab.
+    pub fn latest(self) -> Self {
+        Self {
+            sort: Sort::Latest,
+            order: None,
+            with_chapters: true,
+            ..self
+        }
+    }
+
+    /// Returns the positions in the library order of the manga matching the filters, sorted.
+    pub fn apply(&self, lib: &LibraryEntry) -> Result<Vec<u32>, String> {
+        let status = self
+            .status
+            .as_deref()
+            .map(|v| {
+                list(v)
+                    .map(str::parse)
+                    .collect::<Result<Vec<MangaStatus>, _>>()
+            })
+            .transpose()?;
+        let tags = self.tags.as_deref().map(normalize_list);
+        let exclude_tags = self.exclude_tags.as_deref().map(normalize_list);
+        let author = self.author.as_deref().map(normalize);
+        let artist = self.artist.as_deref().map(normalize);
+
+        let matches = |manga: &MangaEntry| {
+            if self.with_chapters && manga.chapters.is_empty() {
+                return false;
+            }
+            if let Some(status) = &status {
+                if !status.contains(&manga.status) {
+                    return false;
+                }
+            }
+            let manga_tags = &manga.normalized.tags;
+            if let Some(tags) = &tags {
+                if !tags.iter().all(|tag| manga_tags.contains(tag)) {
+                    return false;
+                }
+            }
+            if let Some(tags) = &exclude_tags {
+                if tags.iter().any(|tag| manga_tags.contains(tag)) {
+                    return false;
+                }
+            }
+            if let Some(author) = &author {
+                if !manga.normalized.authors.contains(author.as_str()) {
+                    return false;
+                }
+            }
+            if let Some(artist) = &artist {
+                if !manga.normalized.artists.contains(artist.as_str()) {
+                    return false;
+                }
+            }
+            true
+        };
+
+        let mut docs = lib
+            .order
+            .iter()
+            .enumerate()
+            .filter(|(_, id)| matches(&lib.mangas[*id]))
+            .map(|(doc, id)| (doc as u32, &*lib.mangas[id]))
+            .collect::<Vec<_>>();
+
+        let descending = match self.order {
+            Some(order) => order == Order::Desc,
+            None => matches!(self.sort, Sort::Added | Sort::Latest),
+        };
+        match self.sort {
+            Sort::Library => sort(&mut docs, descending, |(doc, _)| *doc),
+            Sort::Title => sort(&mut docs, descending, |&(_, manga)| {
+                manga.normalized.title.as_str()
+            }),
+            Sort::Added => sort(&mut docs, descending, |(_, manga)| manga.added),
+            Sort::Latest => sort(&mut docs, descending, |(_, manga)| manga.latest_chapter),
+        }
+
+        Ok(docs.into_iter().map(|(doc, _)| doc).collect())
+    }
+}
+
+/// Sorts by the key computed once per item, keeping ties in library order either way.
+fn sort<T, K: Ord>(items: &mut Vec<T>, descending: bool, key: impl Fn(&T) -> K) {
+    let mut keyed = items.drain(..).map(|v| (key(&v), v)).collect::<Vec<_>>();
+    keyed.sort_by(|(a, _), (b, _)| match descending {
+        true => b.cmp(a),
+        false => a.cmp(b),
+    });
+    items.extend(keyed.into_iter().map(|(_, v)| v));
+}
+
+fn list(v: &str) -> impl Iterator<Item = &str> {
+    v.split(',').map(str::trim).filter(|v| !v.is_empty())
+}
@@ -0,0 +1,133 @@
+use http::Request;
+use hyper::Body;
+use serde::{
+    de::{DeserializeOwned, Error as _},
+    Deserialize, Deserializer,
+};
+
+use super::Error;
+
+/// Number of results per page when `limit` is not given.
+const DEFAULT_LIMIT: usize = 50;
+/// Maximum number of results per page.
+const MAX_LIMIT: usize = 500;
+
+/// Deserializes the query string of a request, responding with `400 Bad Request` if it is invalid.
+///
+/// Unknown parameters are ignored, so several structs can be read from the same query.
+pub fn parse_query<T: DeserializeOwned>(req: &Request<Body>) -> Result<T, Error> {
+    serde_urlencoded::from_str(req.uri().query().unwrap_or("")).map_err(|_| Error::BAD_REQUEST)
+}
+
+/// Returns `true` if the query string has no parameters other than `names`.
+pub fn only_params(req: &Request<Body>, names: &[&str]) -> bool {
+    serde_urlencoded::from_str::<Vec<(String, String)>>(req.uri().query().unwrap_or(""))
+        .is_ok_and(|params| params.iter().all(|(k, _)| names.contains(&k.as_str())))
+}
+
+/// The `details` query parameter, listing more information on each manga than its title.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct Details {
+    #[serde(deserialize_with = "flag")]
+    pub details: bool,
+}
+
+/// Deserializes a boolean query parameter, written as `true` or `1`, `false` or `0`.
+pub fn flag<'de, D: Deserializer<'de>>(de: D) -> Result<bool, D::Error> {
+    match &*String::deserialize(de)? {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        v => Err(D::Error::custom(format!("invalid flag {:?}", v))),
+    }
+}
+
+/// The `page` (starting at 1) and `limit` query parameters of a paginated listing.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct Paging {
+    page: usize,
+    limit: usize,
+}
+
+impl Default for Paging {
+    fn default() -> Self {
+        Self {
+            page: 1,
+            limit: DEFAULT_LIMIT,
+        }
+    }
+}
+
+impl Paging {
+    pub fn from_request(req: &Request<Body>) -> Result<Self, Error> {
+        let paging: Self = parse_query(req)?;
+        if paging.page == 0 || paging.limit == 0 || paging.limit > MAX_LIMIT {
+            return Err(Error::BAD_REQUEST);
+        }
+        Ok(paging)
+    }
+
+    /// Returns the items on this page, and whether there are more after it.
+    pub fn apply<'a, T>(&self, items: &'a [T]) -> (&'a [T], bool) {
+        let start = (self.page - 1).saturating_mul(self.limit).min(items.len());
+        let end = start.saturating_add(self.limit).min(items.len());
+        (&items[start..end], end < items.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(query: &str) -> Request<Body> {
+        Request::get(format!("/?{}", query))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn paging(query: &str) -> Option<(usize, usize)> {
+        Paging::from_request(&request(query))
+            .ok()
+            .map(|v| (v.page, v.limit))
+    }
+
+    #[test]
+    fn paging_params() {
+        assert_eq!(paging(""), Some((1, DEFAULT_LIMIT)));
+        assert_eq!(paging("page=3&limit=10&sort=title"), Some((3, 10)));
+        assert_eq!(paging("limit=500"), Some((1, 500)));
+        assert_eq!(paging("page=0"), None);
+        assert_eq!(paging("limit=0"), None);
+        assert_eq!(paging("limit=501"), None);
+        assert_eq!(paging("page=-1"), None);
+    }
+
+    #[test]
+    fn paging_apply() {
+        let items = (0..25).collect::<Vec<_>>();
+        let page = |page, limit| Paging { page, limit }.apply(&items);
+        assert_eq!(page(1, 10), (&items[..10], true));
+        assert_eq!(page(3, 10), (&items[20..], false));
+        assert_eq!(page(1, 25), (&items[..], false));
+        assert_eq!(page(4, 10), (&[][..], false));
+        assert_eq!(page(usize::MAX, MAX_LIMIT), (&[][..], false));
+    }
+
+    #[test]
+    fn only_params_filter() {
+        assert!(only_params(&request(""), &[]));
+        assert!(only_params(&request("details=1"), &["details"]));
+        assert!(!only_params(&request("details=1&page=2"), &["details"]));
+    }
+
+    #[test]
+    fn flags() {
+        let details = |query| parse_query::<Details>(&request(query)).map(|v| v.details);
+        assert!(matches!(details(""), Ok(false)));
+        assert!(matches!(details("details=1"), Ok(true)));
+        assert!(matches!(details("details=true"), Ok(true)));
+        assert!(matches!(details("details=0"), Ok(false)));
+        assert!(details("details=yes").is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use std::ops::Range;
+
+use http::{
+    header::{IF_RANGE, RANGE},
+    HeaderMap,
+};
+
+use super::cache::Validators;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ByteRange {
+    /// The whole body should be sent.
+    Full,
+    /// Only the given part of the body should be sent.
+    Partial(Range<u64>),
+    /// The requested range can't be served.
+    Unsatisfiable,
+}
+
+/// Determines which part of a body of length `len` was requested
+/// using the `Range` and `If-Range` headers.
+///
+/// Malformed or unknown ranges are ignored, as allowed by RFC 9110.
+/// Multiple ranges are not supported and are rejected.
+pub fn byte_range(headers: &HeaderMap, len: u64, validators: &Validators) -> ByteRange {
+    let Some(range) = headers.get(RANGE) else { return ByteRange::Full };
+
+    if let Some(if_range) = headers.get(IF_RANGE) {
+        if !if_range
+            .to_str()
+            .is_ok_and(|v| validators.if_range_matches(v))
+        {
+            return ByteRange::Full;
+        }
+    }
+
+    let Some(spec) = range
+        .to_str()
+        .ok()
+        .and_then(|v| v.trim().strip_prefix("bytes="))
+    else {
+        return ByteRange::Full;
+    };
+
+    if spec.contains(',') {
+        return ByteRange::Unsatisfiable;
+    }
+
+    let Some((start, end)) = spec.split_once('-') else { return ByteRange::Full };
+    let (start, end) = (start.trim(), end.trim());
+
+    let range = if start.is_empty() {
+        // suffix range, `bytes=-n`
+        let Ok(suffix) = end.parse::<u64>() else { return ByteRange::Full };
+        if suffix == 0 || len == 0 {
+            return ByteRange::Unsatisfiable;
+        }
+        len.saturating_sub(suffix)..len
+    } else {
+        let Ok(start) = start.parse::<u64>() else { return ByteRange::Full };
+        let end = if end.is_empty() {
+            len
+        } else {
+            let Ok(end) = end.parse::<u64>() else { return ByteRange::Full };
+            if end < start {
+                return ByteRange::Full;
+            }
+            end.saturating_add(1).min(len)
+        };
+
+        if start >= len {
+            return ByteRange::Unsatisfiable;
+        }
+        start..end
+    };
+
+    ByteRange::Partial(range)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use http::{header::ETAG, HeaderValue};
+
+    use super::*;
+
+    fn validators() -> Validators {
+        Validators::new("body", Some(UNIX_EPOCH + Duration::from_secs(1 << 30)))
+    }
+
+    fn range(headers: &[(&str, &str)], len: u64) -> ByteRange {
+        let headers = headers
+            .iter()
+            .map(|&(name, value)| (name.parse().unwrap(), HeaderValue::from_str(value).unwrap()))
+            .collect();
+        byte_range(&headers, len, &validators())
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(range(&[], 100), ByteRange::Full);
+        assert_eq!(
+            range(&[("range", "bytes=0-9")], 100),
+            ByteRange::Partial(0..10)
+        );
+        assert_eq!(
+            range(&[("range", "bytes=90-")], 100),
+            ByteRange::Partial(90..100)
+        );
+        assert_eq!(
+            range(&[("range", "bytes=90-200")], 100),
+            ByteRange::Partial(90..100)
+        );
+        assert_eq!(
+            range(&[("range", "bytes=-10")], 100),
+            ByteRange::Partial(90..100)
+        );
+        assert_eq!(
+            range(&[("range", "bytes=-200")], 100),
+            ByteRange::Partial(0..100)
+        );
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        assert_eq!(
+            range(&[("range", "bytes=100-")], 100),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(
+            range(&[("range", "bytes=-0")], 100),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(range(&[("range", "bytes=-5")], 0), ByteRange::Unsatisfiable);
+        assert_eq!(
+            range(&[("range", "bytes=0-1,5-6")], 100),
+            ByteRange::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn malformed_ranges_are_ignored() {
+        assert_eq!(range(&[("range", "items=0-9")], 100), ByteRange::Full);
+        assert_eq!(range(&[("range", "bytes=9-0")], 100), ByteRange::Full);
+        assert_eq!(range(&[("range", "bytes=a-b")], 100), ByteRange::Full);
+        assert_eq!(range(&[("range", "bytes=10")], 100), ByteRange::Full);
+    }
+
+    #[test]
+    fn if_range() {
+        let mut headers = HeaderMap::new();
+        validators().apply(&mut headers);
+        let etag = headers[ETAG].to_str().unwrap();
+        let date = "Sat, 10 Jan 2004 13:37:04 GMT";
+
+        assert_eq!(
+            range(&[("range", "bytes=0-9"), ("if-range", etag)], 100),
+            ByteRange::Partial(0..10)
+        );
+        assert_eq!(
+            range(&[("range", "bytes=0-9"), ("if-range", date)], 100),
+            ByteRange::Partial(0..10)
+        );
+        assert_eq!(
+            range(&[("range", "bytes=0-9"), ("if-range", "\"other\"")], 100),
+            ByteRange::Full
+        );
+        assert_eq!(
+            range(&[("range", "bytes=0-9"), ("if-range", "W/\"other\"")], 100),
+            ByteRange::Full
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader},
+    path::PathBuf,
+    sync::{Arc, Mutex, PoisonError, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use hyper::server::accept::{self, Accept};
+use log::{debug, error, info};
+use tokio::{net::TcpListener, sync::mpsc};
+use tokio_rustls::{
+    rustls::{
+        server::{ClientHello, ResolvesServerCert},
+        sign::{self, CertifiedKey},
+        Certificate, PrivateKey, ServerConfig,
+    },
+    server::TlsStream,
+    TlsAcceptor,
+};
+
+/// How often the certificate files are checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long a client may take to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// PEM encoded certificate chain and private key files.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// Builds a TLS acceptor advertising HTTP/2 and HTTP/1.1 over ALPN.
+///
+/// The certificate is reloaded in the background whenever its files change.
+pub fn acceptor(config: TlsConfig) -> anyhow::Result<TlsAcceptor> {
+    let resolver = Arc::new(CertResolver::new(config)?);
+
+    tokio::spawn({
+        let resolver = resolver.clone();
+        async move {
+            let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+            loop {
+                interval.tick().await;
+                let resolver = resolver.clone();
+                let _ = tokio::task::spawn_blocking(move || resolver.reload_if_changed()).await;
+            }
+        }
+    });
+
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Accepts TLS connections on `tcp`.
+///
+/// Handshakes run concurrently, so slow clients don't hold up other connections.
+pub fn incoming(
+    tcp: TcpListener,
+    acceptor: TlsAcceptor,
+) -> impl Accept<Conn = TlsStream<tokio::net::TcpStream>, Error = io::Error> {
+    let (tx, rx) = mpsc::channel(64);
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, addr) = match tcp.accept().await {
+                Ok(v) => v,
+                Err(e) => {
+                    // e.g. too many open files, give the server time to close some
+                    error!("error accepting connection: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+
+            let acceptor = acceptor.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = tx.send(Ok(stream)).await;
+                    }
+                    Ok(Err(e)) => debug!("{}: TLS handshake failed: {}", addr, e),
+                    Err(_) => debug!("{}: TLS handshake timed out", addr),
+                }
+            });
+        }
+    });
+
+    accept::from_stream(futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|v| (v, rx))
+    }))
+}
+
+/// Serves the current certificate, reloading it when its files change.
+struct CertResolver {
+    config: TlsConfig,
+    current: RwLock<Arc<CertifiedKey>>,
+    modified: Mutex<[Option<SystemTime>; 2]>,
+}
+
+impl CertResolver {
+    fn new(config: TlsConfig) -> anyhow::Result<Self> {
+        let modified = Self::modified(&config);
+        let current = load_certified_key(&config)?;
+
+        Ok(Self {
+            config,
+            current: RwLock::new(Arc::new(current)),
+            modified: Mutex::new(modified),
+        })
+    }
+
+    fn modified(config: &TlsConfig) -> [Option<SystemTime>; 2] {
+        [&config.cert, &config.key].map(|path| fs::metadata(path).and_then(|v| v.modified()).ok())
+    }
+
+    /// Reloads the certificate if its files were modified since the last attempt.
+    ///
+    /// The old certificate keeps being served if the new one can't be loaded.
+    fn reload_if_changed(&self) {
+        let modified = Self::modified(&self.config);
+        {
+            let mut last = self.modified.lock().unwrap_or_else(PoisonError::into_inner);
+            if *last == modified {
+                return;
+            }
+            *last = modified;
+        }
+
+        match load_certified_key(&self.config) {
+            Ok(key) => {
+                *self.current.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(key);
+                info!("{:?}: reloaded TLS certificate", self.config.cert);
+            }
+            Err(e) => error!("{:#}", e),
+        }
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(
+            self.current
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
+        )
+    }
+}
+
+fn load_certified_key(config: &TlsConfig) -> anyhow::Result<CertifiedKey> {
+    let certs = (|| {
+        let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(&config.cert)?))?;
+        anyhow::ensure!(!certs.is_empty(), "no certificates found");
+        Ok(certs.into_iter().map(Certificate).collect::<Vec<_>>())
+    })()
+    .with_context(|| format!("{:?}: error loading TLS certificate", config.cert))?;
+
+    let key = (|| {
+        let mut reader = BufReader::new(File::open(&config.key)?);
+        loop {
+            use rustls_pemfile::Item;
+            match rustls_pemfile::read_one(&mut reader)? {
+                Some(Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key)) => {
+                    return Ok(sign::any_supported_type(&PrivateKey(key))?)
+                }
+                Some(_) => continue,
+                None => anyhow::bail!("no private key found"),
+            }
+        }
+    })()
+    .with_context(|| format!("{:?}: error loading TLS private key", config.key))?;
+
+    Ok(CertifiedKey::new(certs, key))
+}
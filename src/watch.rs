@@ -0,0 +1,131 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+    thread,
+    time::Duration,
+};
+
+use anyhow::Context;
+use log::{error, info};
+use notify::{RecursiveMode, Watcher};
+use walkdir::WalkDir;
+
+use crate::load::{CoverEntry, Library, LibraryEntry};
+
+/// How long to wait for more changes before reloading,
+/// so copying a whole chapter only reloads its manga once.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Watches the library roots and reloads the manga affected by changes on disk.
+///
+/// The watcher runs on its own thread for the rest of the process.
+pub fn spawn(lib: Arc<Library>) -> anyhow::Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher =
+        notify::recommended_watcher(tx).context("Failed to create file system watcher")?;
+
+    let roots = lib.get().roots.clone();
+    for root in &roots {
+        watcher
+            .watch(root, RecursiveMode::Recursive)
+            .with_context(|| format!("{:?}: error watching library root", root))?;
+        info!("{:?}: watching for changes", root);
+    }
+
+    thread::Builder::new()
+        .name("watcher".into())
+        .spawn(move || {
+            let _watcher = watcher;
+
+            while let Ok(event) = rx.recv() {
+                let mut changed = BTreeSet::new();
+                let mut event = Some(event);
+                while let Some(res) = event {
+                    match res {
+                        Ok(event) => changed.extend(event.paths),
+                        Err(e) => error!("error watching library: {}", e),
+                    }
+                    event = rx.recv_timeout(DEBOUNCE).ok();
+                }
+
+                let opts = lib.opts();
+                lib.update(|lib| {
+                    let dirs = affected_manga(lib, &changed);
+                    if dirs.is_empty() {
+                        return Ok(None);
+                    }
+
+                    let mut lib = lib.clone();
+                    lib.reload_manga(&dirs, &changed, opts);
+                    lib.rebuild_listing()?;
+                    Ok(Some(lib))
+                });
+            }
+        })
+        .context("Failed to spawn watcher thread")?;
+
+    Ok(())
+}
+
+/// Finds the manga directories that need reloading after `changed` paths were modified.
+fn affected_manga(lib: &LibraryEntry, changed: &BTreeSet<PathBuf>) -> BTreeSet<PathBuf> {
+    let mut dirs = BTreeSet::new();
+
+    for path in changed {
+        // manga already in the library, including ones whose directory was removed
+        for manga in lib.mangas.values() {
+            let cover = match &manga.cover {
+                Some(CoverEntry::File(page)) => page.path == *path,
+                _ => false,
+            };
+            if path.starts_with(&manga.path)
+                || (manga.path.starts_with(path) && !path.exists())
+                || manga.chapters.iter().any(|ch| path.starts_with(&ch.path))
+                || cover
+            {
+                dirs.insert(manga.path.clone());
+            }
+        }
+
+        // the manga containing the path, which may be new
+        if let Some(dir) = path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| lib.roots.iter().any(|root| dir.starts_with(root)))
+            .find(|dir| dir.join("info.toml").is_file())
+        {
+            dirs.insert(dir.to_owned());
+        }
+
+        // new manga inside a directory that was created or moved in
+        if path.is_dir() {
+            dirs.extend(find_manga(path));
+        }
+    }
+
+    dirs
+}
+
+/// Finds the manga directories under `path`.
+fn find_manga(path: &Path) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+
+    let mut walk = WalkDir::new(path)
+        .max_open(128)
+        .follow_links(true)
+        .into_iter()
+        .filter_entry(|entry| entry.file_type().is_dir());
+
+    while let Some(entry) = walk.next() {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        if entry.path().join("info.toml").is_file() {
+            dirs.push(entry.into_path());
+            walk.skip_current_dir();
+        }
+    }
+
+    dirs
+}